@@ -1,62 +1,244 @@
 use anyhow::Result;
+use std::{env, ops::Range};
 
-pub fn color_to_ansi(color: &str) -> Result<String> {
-    if color.len() != 2 {
-        return Err(anyhow::format_err!("Invalid color code: {}", color));
+use crate::{
+    ast::{Attributes, Line, Span},
+    layout::Layout,
+    parser::{parse_spans, RuleColors},
+};
+
+// How many colors the terminal can show. DOS colors are drawn with the exact
+// VGA palette when it can, rather than the terminal's own idea of each color.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorDepth {
+    Basic,
+    Palette256,
+    TrueColor,
+}
+
+impl ColorDepth {
+    // Terminals with 24-bit color advertise it in `COLORTERM`
+    pub fn detect() -> Self {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let term = env::var("TERM").unwrap_or_default();
+
+        match colorterm.as_str() {
+            "truecolor" | "24bit" => ColorDepth::TrueColor,
+            _ if term.contains("256color") => ColorDepth::Palette256,
+            _ => ColorDepth::Basic,
+        }
     }
+}
+
+// Render the whole document for a terminal or pipe, one row per line. Each
+// row resets the colors so nothing leaks into the next line.
+pub fn document_to_ansi(
+    document: &[Line],
+    width: u16,
+    color: bool,
+    rule: RuleColors,
+    depth: ColorDepth,
+) -> String {
+    let mut result = String::new();
 
-    let mut color_chars = color.chars();
+    for row in Layout::new(document, width, rule, depth).rows {
+        if color {
+            result.push_str(&spans_to_ansi(&row.spans, depth));
+            result.push_str("\x1B[0m");
+        } else {
+            row.spans
+                .iter()
+                .for_each(|span| result.push_str(&span.text));
+        }
 
-    let bg = color_chars.nth(0).unwrap().to_digit(16);
-    let fg = color_chars.nth(0).unwrap().to_digit(16);
+        result.push('\n');
+    }
 
-    Ok(format!(
-        "\x1B[{}m\x1B[1;{};{}m",
-        color_to_blink(bg)?,
-        color_to_ansi_color(fg, false)?,
-        color_to_ansi_color(bg, true)?,
-    ))
+    result
 }
 
-const DOS_COLORS: [u32; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+pub fn spans_to_ansi(spans: &[Span], depth: ColorDepth) -> String {
+    let mut result = String::new();
 
-// Convert a DOS color code to the ANSI equivalent
-fn color_to_ansi_color(color: Option<u32>, is_bg: bool) -> Result<u32> {
-    let color_code = match (color, is_bg) {
-        (Some(color), false) if color < 8 => 30 + DOS_COLORS[color as usize],
-        (Some(color), false) if color < 16 => 90 + DOS_COLORS[color as usize % 8],
-        (Some(color), true) if color < 16 => 40 + DOS_COLORS[color as usize >> 1],
-        _ => return Err(anyhow::format_err!("Invalid color code: {:#?}", color)),
-    };
+    for span in spans {
+        result.push_str(&attributes_to_ansi(&span.attributes, depth));
+        result.push_str(&span.text);
+    }
 
-    Ok(color_code)
+    result
 }
 
-// Convert a DOS background color
-fn color_to_blink(color: Option<u32>) -> Result<u32> {
-    let blink_code = match color {
-        Some(color) if color % 2 == 0 => 25,
-        Some(color) if color % 2 == 1 => 5,
-        _ => return Err(anyhow::format_err!("Invalid color code: {:#?}", color)),
+// Render spans with the given visible column ranges in reverse video
+pub fn highlight_spans(spans: &[Span], ranges: &[Range<usize>], depth: ColorDepth) -> String {
+    let mut result = String::new();
+    let mut column = 0;
+    let mut reversed = false;
+
+    for span in spans {
+        result.push_str(&attributes_to_ansi(&span.attributes, depth));
+
+        for c in span.text.chars() {
+            let highlight = ranges.iter().any(|range| range.contains(&column));
+
+            if highlight != reversed {
+                result.push_str(if highlight { "\x1B[7m" } else { "\x1B[27m" });
+                reversed = highlight;
+            }
+
+            result.push(c);
+            column += 1;
+        }
+    }
+
+    if reversed {
+        result.push_str("\x1B[27m");
+    }
+
+    result
+}
+
+// Convert a line of `~BF` color markup to ANSI escape codes
+pub fn line_to_ansi(line: &str, depth: ColorDepth) -> Result<String> {
+    Ok(spans_to_ansi(&parse_spans(line)?, depth))
+}
+
+// ANSI color numbers in DOS order, which maps both ways
+pub(crate) const DOS_COLORS: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+// The standard VGA text mode palette, indexed by DOS color
+pub const VGA_PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0xAA),
+    (0x00, 0xAA, 0x00),
+    (0x00, 0xAA, 0xAA),
+    (0xAA, 0x00, 0x00),
+    (0xAA, 0x00, 0xAA),
+    (0xAA, 0x55, 0x00),
+    (0xAA, 0xAA, 0xAA),
+    (0x55, 0x55, 0x55),
+    (0x55, 0x55, 0xFF),
+    (0x55, 0xFF, 0x55),
+    (0x55, 0xFF, 0xFF),
+    (0xFF, 0x55, 0x55),
+    (0xFF, 0x55, 0xFF),
+    (0xFF, 0xFF, 0x55),
+    (0xFF, 0xFF, 0xFF),
+];
+
+pub fn attributes_to_ansi(attributes: &Attributes, depth: ColorDepth) -> String {
+    // The basic colors are drawn bold, which the exact colors have no need for
+    let bold = if depth == ColorDepth::Basic { "1;" } else { "" };
+
+    format!(
+        "\x1B[{}m\x1B[{}{};{}m",
+        if attributes.blink { 5 } else { 25 },
+        bold,
+        color_to_ansi(attributes.foreground, false, depth),
+        color_to_ansi(attributes.background, true, depth),
+    )
+}
+
+// The SGR parameters selecting a DOS color as the foreground or background,
+// which only has the first 8 colors
+fn color_to_ansi(color: u8, is_bg: bool, depth: ColorDepth) -> String {
+    let color = if is_bg { color % 8 } else { color };
+    let layer = if is_bg { 48 } else { 38 };
+
+    match depth {
+        ColorDepth::Basic => color_to_ansi_color(color, is_bg).to_string(),
+        ColorDepth::Palette256 => format!("{};5;{}", layer, nearest_256_color(color)),
+        ColorDepth::TrueColor => {
+            let (r, g, b) = VGA_PALETTE[color as usize];
+            format!("{};2;{};{};{}", layer, r, g, b)
+        }
+    }
+}
+
+// The closest entry to a DOS color in the 6x6x6 cube and grey ramp of the
+// 256 color palette. The first 16 entries are left out since terminals
+// change them.
+pub fn nearest_256_color(color: u8) -> u8 {
+    let (r, g, b) = VGA_PALETTE[color as usize];
+    let distance = |(r2, g2, b2): (u8, u8, u8)| {
+        [(r, r2), (g, g2), (b, b2)]
+            .iter()
+            .map(|&(a, b)| (a as i32 - b as i32).pow(2))
+            .sum::<i32>()
     };
 
-    Ok(blink_code)
+    (16..=255)
+        .min_by_key(|&index| distance(palette_256_rgb(index)))
+        .unwrap()
+}
+
+fn palette_256_rgb(index: u8) -> (u8, u8, u8) {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+    match index {
+        16..=231 => {
+            let i = index - 16;
+            (
+                LEVELS[(i / 36) as usize],
+                LEVELS[(i / 6 % 6) as usize],
+                LEVELS[(i % 6) as usize],
+            )
+        }
+        _ => {
+            let level = 8 + (index - 232) * 10;
+            (level, level, level)
+        }
+    }
+}
+
+// Convert a DOS color code to the ANSI equivalent
+fn color_to_ansi_color(color: u8, is_bg: bool) -> u8 {
+    match (color, is_bg) {
+        (color, false) if color < 8 => 30 + DOS_COLORS[color as usize],
+        (color, false) => 90 + DOS_COLORS[color as usize % 8],
+        (color, true) => 40 + DOS_COLORS[color as usize % 8],
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn code_to_ansi(color: &str, depth: ColorDepth) -> Result<String> {
+        Ok(attributes_to_ansi(&Attributes::parse(color)?, depth))
+    }
+
     #[test]
     fn test_color_to_ansi() -> Result<()> {
-        assert_eq!(color_to_ansi("00")?, "\x1B[25m\x1B[1;30;40m");
-        assert_eq!(color_to_ansi("0f")?, "\x1B[25m\x1B[1;97;40m");
-        assert_eq!(color_to_ansi("1f")?, "\x1B[5m\x1B[1;97;40m");
-        assert_eq!(color_to_ansi("ff")?, "\x1B[5m\x1B[1;97;47m");
-
-        assert!(color_to_ansi("0").is_err());
-        assert!(color_to_ansi("000").is_err());
-        assert!(color_to_ansi("gg").is_err());
+        let basic = ColorDepth::Basic;
+
+        assert_eq!(code_to_ansi("00", basic)?, "\x1B[25m\x1B[1;30;40m");
+        assert_eq!(code_to_ansi("0f", basic)?, "\x1B[25m\x1B[1;97;40m");
+        assert_eq!(code_to_ansi("1f", basic)?, "\x1B[5m\x1B[1;97;40m");
+        assert_eq!(code_to_ansi("ff", basic)?, "\x1B[5m\x1B[1;97;47m");
+
+        assert!(code_to_ansi("0", basic).is_err());
+        assert!(code_to_ansi("000", basic).is_err());
+        assert!(code_to_ansi("gg", basic).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exact_colors() -> Result<()> {
+        // Brown is VGA's dark yellow, not the terminal's yellow
+        assert_eq!(
+            code_to_ansi("26", ColorDepth::TrueColor)?,
+            "\x1B[25m\x1B[38;2;170;85;0;48;2;0;0;170m"
+        );
+        assert_eq!(
+            code_to_ansi("9e", ColorDepth::Palette256)?,
+            "\x1B[5m\x1B[38;5;227;48;5;124m"
+        );
+
+        assert_eq!(nearest_256_color(0), 16);
+        assert_eq!(nearest_256_color(6), 130);
+        assert_eq!(nearest_256_color(8), 240);
+        assert_eq!(nearest_256_color(15), 231);
 
         Ok(())
     }
@@ -0,0 +1,82 @@
+use anyhow::{format_err, Result};
+
+// The colors of a run of text, as given by a `BF` color code where B holds the
+// background color and blink bit and F the foreground color
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Attributes {
+    pub foreground: u8,
+    pub background: u8,
+    pub blink: bool,
+}
+
+impl Attributes {
+    pub fn parse(code: &str) -> Result<Self> {
+        let mut digits = code.chars().map(|c| c.to_digit(16));
+
+        match (digits.next(), digits.next(), digits.next()) {
+            (Some(Some(bg)), Some(Some(fg)), None) => Ok(Self {
+                foreground: fg as u8,
+                background: (bg >> 1) as u8,
+                blink: bg % 2 == 1,
+            }),
+            _ => Err(format_err!("Invalid color code: {}", code)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub attributes: Attributes,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Button {
+    pub target: usize,
+    // Shown instead of "Click Here" when given
+    pub label: Option<String>,
+    // The anchor named as the target, if it was not a line number
+    pub anchor: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Span(Span),
+    // þBUTTON with the document line it jumps to
+    Button(Button),
+    // þLINE with its line type
+    Rule(u32),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Line {
+    pub nodes: Vec<Node>,
+}
+
+impl Line {
+    // Rules take up the whole line, ignoring anything else on it
+    pub fn rule(&self) -> Option<u32> {
+        self.nodes.iter().find_map(|node| match node {
+            Node::Rule(kind) => Some(*kind),
+            _ => None,
+        })
+    }
+
+    // The text of the spans on the line, without buttons or rules
+    pub fn text(&self) -> String {
+        self.nodes
+            .iter()
+            .filter_map(|node| match node {
+                Node::Span(span) => Some(span.text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+// A section of the document, starting at `line`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heading {
+    pub line: usize,
+    pub title: String,
+}
@@ -0,0 +1,60 @@
+use crate::{
+    ast::Span,
+    parser::{metadata, parse_spans},
+};
+
+// Vendor branding set by metadata directives in the document. Anything not
+// set falls back to the original CatGen branding.
+#[derive(Debug, Default, PartialEq)]
+pub struct Branding {
+    // þTITLE, shown in the footer and used as the title of exports
+    pub title: Option<String>,
+    // þLOGO, color markup shown in the middle of the footer
+    pub logo: Option<Vec<Span>>,
+    // One þEXIT line of color markup for each line of the exit screen
+    pub exit_screen: Option<Vec<Vec<Span>>>,
+}
+
+// Collect the branding directives of a document. Markup that does not parse
+// is left out, and reported by `catgen check`.
+pub fn find_branding(contents: &str) -> Branding {
+    let mut branding = Branding::default();
+
+    for line in contents.lines() {
+        let Some((name, value, _)) = metadata(line) else {
+            continue;
+        };
+
+        match name {
+            "TITLE" => branding.title = Some(value.trim().to_string()),
+            "LOGO" => branding.logo = parse_spans(value).ok(),
+            "EXIT" => branding
+                .exit_screen
+                .get_or_insert_with(Vec::new)
+                .push(parse_spans(value).unwrap_or_default()),
+            _ => {}
+        }
+    }
+
+    branding
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_branding() {
+        let branding = find_branding(
+            "07þTITLE  Acme Catalog \n0FþLOGO 0CAcme~0F Corp\n03Hello\nþEXIT 0FBye\nþEXIT\n",
+        );
+
+        assert_eq!(branding.title.as_deref(), Some("Acme Catalog"));
+        assert_eq!(branding.logo, Some(parse_spans("0CAcme~0F Corp").unwrap()));
+        assert_eq!(
+            branding.exit_screen,
+            Some(vec![parse_spans("0FBye").unwrap(), Vec::new()])
+        );
+        assert_eq!(find_branding("03Hello"), Branding::default());
+    }
+}
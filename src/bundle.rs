@@ -0,0 +1,133 @@
+use anyhow::{bail, Context, Result};
+use std::{
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+// A bundled viewer is the viewer executable followed by the document, its
+// length as a little endian u64 and this marker.
+const MAGIC: &[u8; 8] = b"CATGEN\x00\x01";
+const TRAILER_LEN: u64 = 16;
+
+// Copy the viewer stub to `output` with the document appended as a trailer
+pub fn build(viewer: &Path, document: &str, output: &Path) -> Result<()> {
+    let stub = read_stub(viewer)?;
+
+    let mut file = File::create(output)
+        .with_context(|| format!("Unable to create viewer: {}", output.display()))?;
+
+    file.write_all(&stub)?;
+    file.write_all(document.as_bytes())?;
+    file.write_all(&(document.len() as u64).to_le_bytes())?;
+    file.write_all(MAGIC)?;
+
+    set_executable(output)
+}
+
+// Read the document appended to an executable, if there is one
+pub fn read_embedded(exe: &Path) -> Result<Option<String>> {
+    let mut file = File::open(exe)?;
+
+    let Some(length) = read_trailer(&mut file)? else {
+        return Ok(None);
+    };
+
+    file.seek(SeekFrom::End(-((TRAILER_LEN + length) as i64)))?;
+
+    let mut document = vec![0; length as usize];
+    file.read_exact(&mut document)?;
+
+    Ok(Some(
+        String::from_utf8(document).context("Embedded catalog is not valid UTF-8")?,
+    ))
+}
+
+// Read the viewer executable, dropping any document already bundled with it
+fn read_stub(viewer: &Path) -> Result<Vec<u8>> {
+    let mut file = File::open(viewer)
+        .with_context(|| format!("Unable to read viewer stub: {}", viewer.display()))?;
+
+    let stub_len = match read_trailer(&mut file)? {
+        Some(length) => file.metadata()?.len() - TRAILER_LEN - length,
+        None => file.metadata()?.len(),
+    };
+
+    let mut stub = Vec::with_capacity(stub_len as usize);
+    file.rewind()?;
+    file.take(stub_len).read_to_end(&mut stub)?;
+
+    Ok(stub)
+}
+
+// Returns the length of the bundled document if the file ends in a trailer
+fn read_trailer(file: &mut File) -> Result<Option<u64>> {
+    let file_len = file.metadata()?.len();
+
+    if file_len < TRAILER_LEN {
+        return Ok(None);
+    }
+
+    let mut trailer = [0; TRAILER_LEN as usize];
+    file.seek(SeekFrom::End(-(TRAILER_LEN as i64)))?;
+    file.read_exact(&mut trailer)?;
+
+    if &trailer[8..] != MAGIC {
+        return Ok(None);
+    }
+
+    let length = u64::from_le_bytes(trailer[..8].try_into()?);
+
+    if length > file_len - TRAILER_LEN {
+        bail!("Corrupt catalog trailer");
+    }
+
+    Ok(Some(length))
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_mode(permissions.mode() | 0o111);
+    fs::set_permissions(path, permissions)?;
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_build_and_read_embedded() -> Result<()> {
+        let dir = env::temp_dir().join(format!("catgen-bundle-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+
+        let stub = dir.join("stub");
+        let first = dir.join("first");
+        let second = dir.join("second");
+        fs::write(&stub, b"#!stub")?;
+
+        assert_eq!(read_embedded(&stub)?, None);
+
+        build(&stub, "0Ffirst", &first)?;
+        assert_eq!(read_embedded(&first)?.as_deref(), Some("0Ffirst"));
+
+        // Rebuilding from a bundled viewer replaces the document
+        build(&first, "0Esecond", &second)?;
+        assert_eq!(read_embedded(&second)?.as_deref(), Some("0Esecond"));
+        assert_eq!(read_stub(&second)?, b"#!stub");
+
+        fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
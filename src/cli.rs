@@ -0,0 +1,423 @@
+use anyhow::{bail, Result};
+use std::{fmt, path::PathBuf};
+
+pub const USAGE: &str =
+    "Usage: catgen [--encoding <auto | utf-8 | cp437>] [--theme <name | file.toml>]
+              [view <file.cat | ->]
+       catgen check <file.cat | ->
+       catgen render <file.cat | -> [--width <columns>] [--color <always | never | auto>]
+       catgen build <file.cat | -> -o <viewer> [--stub <viewer>]
+       catgen export --html <file.cat | -> [-o <output>] [--blink]
+       catgen export --pdf <file.cat | -> [-o <output>]
+       catgen import <file.ans | -> [-o <file.cat>]";
+
+// Where the catalog document should be read from
+#[derive(Debug, PartialEq)]
+pub enum Source {
+    Builtin,
+    Stdin,
+    File(PathBuf),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Builtin => write!(f, "<builtin>"),
+            Source::Stdin => write!(f, "<stdin>"),
+            Source::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+// The character set of documents read from files or stdin
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Auto,
+    Utf8,
+    Cp437,
+}
+
+// Options that apply to every command
+#[derive(Debug, PartialEq)]
+pub struct GlobalOptions {
+    pub encoding: Encoding,
+    pub theme: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ColorMode {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExportFormat {
+    Html { blink: bool },
+    Pdf,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    View(Source),
+    Check(Source),
+    Render {
+        source: Source,
+        width: Option<u16>,
+        color: ColorMode,
+    },
+    Build {
+        source: Source,
+        output: PathBuf,
+        stub: Option<PathBuf>,
+    },
+    Export {
+        format: ExportFormat,
+        source: Source,
+        output: Option<PathBuf>,
+    },
+    Import {
+        source: Source,
+        output: Option<PathBuf>,
+    },
+}
+
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let command = match args.next().as_deref() {
+        None => Command::View(Source::Builtin),
+        Some("view") => Command::View(parse_source(args.next())?),
+        Some("check") => Command::Check(parse_source(args.next())?),
+        Some("render") => parse_render(&mut args)?,
+        Some("build") => parse_build(&mut args)?,
+        Some("export") => parse_export(&mut args)?,
+        Some("import") => parse_import(&mut args)?,
+        Some(other) => bail!("Unknown command: {}\n{}", other, USAGE),
+    };
+
+    if let Some(extra) = args.next() {
+        bail!("Unexpected argument: {}\n{}", extra, USAGE);
+    }
+
+    Ok(command)
+}
+
+// Remove the options that apply to every command from the arguments so the
+// commands can be parsed without knowing about them
+pub fn take_global_options(
+    mut args: impl Iterator<Item = String>,
+) -> Result<(Vec<String>, GlobalOptions)> {
+    let mut rest = Vec::new();
+    let mut options = GlobalOptions {
+        encoding: Encoding::Auto,
+        theme: None,
+    };
+
+    while let Some(arg) = args.next() {
+        let (flag, value) = split_flag(&arg);
+
+        match flag {
+            "--encoding" => {
+                let value = flag_value(flag, value.or_else(|| args.next()))?;
+
+                options.encoding = match value.to_lowercase().as_str() {
+                    "auto" => Encoding::Auto,
+                    "utf-8" | "utf8" => Encoding::Utf8,
+                    "cp437" | "ibm437" => Encoding::Cp437,
+                    other => bail!("Unknown encoding: {}\n{}", other, USAGE),
+                };
+            }
+            "--theme" => {
+                options.theme = Some(flag_value(flag, value.or_else(|| args.next()))?);
+            }
+            _ => rest.push(arg),
+        }
+    }
+
+    Ok((rest, options))
+}
+
+fn parse_render(args: &mut impl Iterator<Item = String>) -> Result<Command> {
+    let mut source = None;
+    let mut width = None;
+    let mut color = ColorMode::Auto;
+
+    while let Some(arg) = args.next() {
+        let (flag, value) = split_flag(&arg);
+
+        match flag {
+            "--width" => {
+                let value = flag_value(flag, value.or_else(|| args.next()))?;
+
+                width = match value.parse::<u16>() {
+                    Ok(width) if width > 0 => Some(width),
+                    _ => bail!("Invalid width: {}", value),
+                };
+            }
+            "--color" => {
+                color = match flag_value(flag, value.or_else(|| args.next()))?.as_str() {
+                    "auto" => ColorMode::Auto,
+                    "always" => ColorMode::Always,
+                    "never" => ColorMode::Never,
+                    other => bail!("Invalid color mode: {}\n{}", other, USAGE),
+                };
+            }
+            _ if source.is_none() => source = Some(parse_source(Some(arg))?),
+            _ => bail!("Unexpected argument: {}\n{}", arg, USAGE),
+        }
+    }
+
+    let Some(source) = source else {
+        bail!("Missing catalog file\n{}", USAGE);
+    };
+
+    Ok(Command::Render {
+        source,
+        width,
+        color,
+    })
+}
+
+fn parse_build(args: &mut impl Iterator<Item = String>) -> Result<Command> {
+    let mut source = None;
+    let mut output = None;
+    let mut stub = None;
+
+    while let Some(arg) = args.next() {
+        let (flag, value) = split_flag(&arg);
+
+        match flag {
+            "-o" | "--output" => {
+                output = Some(flag_value(flag, value.or_else(|| args.next()))?.into())
+            }
+            "--stub" => stub = Some(flag_value(flag, value.or_else(|| args.next()))?.into()),
+            _ if source.is_none() => source = Some(parse_source(Some(arg))?),
+            _ => bail!("Unexpected argument: {}\n{}", arg, USAGE),
+        }
+    }
+
+    let Some(source) = source else {
+        bail!("Missing catalog file\n{}", USAGE);
+    };
+
+    let Some(output) = output else {
+        bail!("Missing output file\n{}", USAGE);
+    };
+
+    Ok(Command::Build {
+        source,
+        output,
+        stub,
+    })
+}
+
+fn parse_export(args: &mut impl Iterator<Item = String>) -> Result<Command> {
+    let mut html = false;
+    let mut pdf = false;
+    let mut blink = false;
+    let mut source = None;
+    let mut output = None;
+
+    while let Some(arg) = args.next() {
+        let (flag, value) = split_flag(&arg);
+
+        match flag {
+            "--html" => html = true,
+            "--pdf" => pdf = true,
+            "--blink" => blink = true,
+            "-o" | "--output" => {
+                output = Some(flag_value(flag, value.or_else(|| args.next()))?.into())
+            }
+            _ if source.is_none() => source = Some(parse_source(Some(arg))?),
+            _ => bail!("Unexpected argument: {}\n{}", arg, USAGE),
+        }
+    }
+
+    let format = match (html, pdf) {
+        (true, false) => ExportFormat::Html { blink },
+        (false, true) => ExportFormat::Pdf,
+        (true, true) => bail!("Choose either --html or --pdf\n{}", USAGE),
+        (false, false) => bail!("Missing export format\n{}", USAGE),
+    };
+
+    let Some(source) = source else {
+        bail!("Missing catalog file\n{}", USAGE);
+    };
+
+    Ok(Command::Export {
+        format,
+        source,
+        output,
+    })
+}
+
+fn parse_import(args: &mut impl Iterator<Item = String>) -> Result<Command> {
+    let mut source = None;
+    let mut output = None;
+
+    while let Some(arg) = args.next() {
+        let (flag, value) = split_flag(&arg);
+
+        match flag {
+            "-o" | "--output" => {
+                output = Some(flag_value(flag, value.or_else(|| args.next()))?.into())
+            }
+            _ if source.is_none() => source = Some(parse_source(Some(arg))?),
+            _ => bail!("Unexpected argument: {}\n{}", arg, USAGE),
+        }
+    }
+
+    let Some(source) = source else {
+        bail!("Missing catalog file\n{}", USAGE);
+    };
+
+    Ok(Command::Import { source, output })
+}
+
+// Accept both `--flag value` and `--flag=value`, leaving other arguments whole
+fn split_flag(arg: &str) -> (&str, Option<String>) {
+    match arg.split_once('=') {
+        Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+        _ => (arg, None),
+    }
+}
+
+fn flag_value(flag: &str, value: Option<String>) -> Result<String> {
+    match value {
+        Some(value) => Ok(value),
+        None => bail!("Missing value for {}\n{}", flag, USAGE),
+    }
+}
+
+fn parse_source(arg: Option<String>) -> Result<Source> {
+    match arg.as_deref() {
+        None => bail!("Missing catalog file\n{}", USAGE),
+        Some("-") => Ok(Source::Stdin),
+        Some(path) => Ok(Source::File(PathBuf::from(path))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<String>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn test_parse_args() -> Result<()> {
+        assert_eq!(parse_args(args(&[]))?, Command::View(Source::Builtin));
+        assert_eq!(
+            parse_args(args(&["view", "-"]))?,
+            Command::View(Source::Stdin)
+        );
+        assert_eq!(
+            parse_args(args(&["view", "catalog.cat"]))?,
+            Command::View(Source::File(PathBuf::from("catalog.cat")))
+        );
+
+        assert_eq!(
+            parse_args(args(&["check", "-"]))?,
+            Command::Check(Source::Stdin)
+        );
+        assert_eq!(
+            parse_args(args(&["build", "-", "-o", "viewer"]))?,
+            Command::Build {
+                source: Source::Stdin,
+                output: PathBuf::from("viewer"),
+                stub: None,
+            }
+        );
+        assert_eq!(
+            parse_args(args(&[
+                "build",
+                "--output=viewer",
+                "--stub",
+                "stub",
+                "a.cat"
+            ]))?,
+            Command::Build {
+                source: Source::File(PathBuf::from("a.cat")),
+                output: PathBuf::from("viewer"),
+                stub: Some(PathBuf::from("stub")),
+            }
+        );
+
+        assert_eq!(
+            parse_args(args(&["export", "--html", "a.cat", "--blink"]))?,
+            Command::Export {
+                format: ExportFormat::Html { blink: true },
+                source: Source::File(PathBuf::from("a.cat")),
+                output: None,
+            }
+        );
+        assert_eq!(
+            parse_args(args(&["export", "--pdf", "--output=a.pdf", "a.cat"]))?,
+            Command::Export {
+                format: ExportFormat::Pdf,
+                source: Source::File(PathBuf::from("a.cat")),
+                output: Some(PathBuf::from("a.pdf")),
+            }
+        );
+
+        assert_eq!(
+            parse_args(args(&["render", "a.cat", "--width=60", "--color", "never"]))?,
+            Command::Render {
+                source: Source::File(PathBuf::from("a.cat")),
+                width: Some(60),
+                color: ColorMode::Never,
+            }
+        );
+
+        assert_eq!(
+            parse_args(args(&["import", "art.ans", "-o", "art.cat"]))?,
+            Command::Import {
+                source: Source::File(PathBuf::from("art.ans")),
+                output: Some(PathBuf::from("art.cat")),
+            }
+        );
+        assert_eq!(
+            parse_args(args(&["import", "-o", "art.cat", "-"]))?,
+            Command::Import {
+                source: Source::Stdin,
+                output: Some(PathBuf::from("art.cat")),
+            }
+        );
+
+        assert_eq!(
+            take_global_options(args(&[
+                "view",
+                "--encoding=CP437",
+                "old.doc",
+                "--theme",
+                "light"
+            ]))?,
+            (
+                vec!["view".to_string(), "old.doc".to_string()],
+                GlobalOptions {
+                    encoding: Encoding::Cp437,
+                    theme: Some("light".to_string()),
+                }
+            )
+        );
+        assert!(take_global_options(args(&["--encoding", "ebcdic"])).is_err());
+        assert!(take_global_options(args(&["--encoding"])).is_err());
+        assert!(take_global_options(args(&["--theme"])).is_err());
+
+        assert!(parse_args(args(&["view"])).is_err());
+        assert!(parse_args(args(&["render", "a.cat", "--width", "0"])).is_err());
+        assert!(parse_args(args(&["render", "a.cat", "--color=sometimes"])).is_err());
+        assert!(parse_args(args(&["export", "a.cat"])).is_err());
+        assert!(parse_args(args(&["export", "--html"])).is_err());
+        assert!(parse_args(args(&["export", "--html", "--pdf", "a.cat"])).is_err());
+        assert!(parse_args(args(&["build", "a.cat"])).is_err());
+        assert!(parse_args(args(&["build", "a.cat", "-o"])).is_err());
+        assert!(parse_args(args(&["view", "a.cat", "b.cat"])).is_err());
+        assert!(parse_args(args(&["bogus"])).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use anyhow::{bail, Context, Result};
+use std::{
+    env, fs,
+    io::{stdin, Read},
+};
+
+use crate::{
+    bundle,
+    cli::{Encoding, Source},
+    cp437, parser,
+};
+
+// Read the raw catalog markup from the requested source. The builtin source
+// prefers a document bundled onto this executable over the sample document.
+pub fn load(source: &Source, builtin: &str, encoding: Encoding) -> Result<String> {
+    match source {
+        Source::Builtin => {
+            let document = bundle::read_embedded(&env::current_exe()?)
+                .context("Unable to read the bundled catalog")?;
+
+            Ok(document.unwrap_or_else(|| builtin.to_string()))
+        }
+        source => decode(&load_bytes(source)?, encoding)
+            .with_context(|| format!("Unable to decode catalog: {}", source)),
+    }
+}
+
+// Turn the bytes of a document into text. Automatic detection falls back to
+// CP437 for anything that is not valid UTF-8, as DOS era catalogs never are.
+pub fn decode(bytes: &[u8], encoding: Encoding) -> Result<String> {
+    let encoding = match encoding {
+        Encoding::Auto if std::str::from_utf8(bytes).is_ok() => Encoding::Utf8,
+        Encoding::Auto => Encoding::Cp437,
+        encoding => encoding,
+    };
+
+    match encoding {
+        Encoding::Cp437 => {
+            // DOS editors mark the end of the file with Ctrl-Z
+            let end = bytes.iter().position(|&byte| byte == 0x1A);
+            let text = cp437::decode(&bytes[..end.unwrap_or(bytes.len())]);
+
+            // The directive marker is byte 0xFE, which CP437 shows as a square
+            Ok(parser::DIRECTIVES.iter().fold(text, |text, directive| {
+                text.replace(&format!("■{}", directive), &format!("þ{}", directive))
+            }))
+        }
+        _ => Ok(String::from_utf8(bytes.to_vec()).context("Not valid UTF-8")?),
+    }
+}
+
+// Read the raw bytes of a file or stdin, for input that is not UTF-8 markup
+pub fn load_bytes(source: &Source) -> Result<Vec<u8>> {
+    match source {
+        Source::Builtin => bail!("The builtin catalog cannot be read as a file"),
+        Source::Stdin => {
+            let mut contents = Vec::new();
+            stdin()
+                .read_to_end(&mut contents)
+                .context("Unable to read catalog from stdin")?;
+
+            Ok(contents)
+        }
+        Source::File(path) => {
+            if !path.exists() {
+                bail!("Catalog file not found: {}", path.display());
+            }
+
+            fs::read(path)
+                .with_context(|| format!("Unable to read catalog file: {}", path.display()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast::Node, branding, parser::parse_document};
+
+    #[test]
+    fn test_decode() -> Result<()> {
+        let dos = b"0F\xC9\xCD\xBB \xFE\r\n07\xFELINE1\r\n03\xFEBUTTON0001\r\n\x1A\x1A";
+
+        assert_eq!(
+            decode(dos, Encoding::Auto)?,
+            "0F╔═╗ ■\r\n07þLINE1\r\n03þBUTTON0001\r\n"
+        );
+        assert_eq!(decode("07þLINE".as_bytes(), Encoding::Auto)?, "07þLINE");
+        assert!(decode(dos, Encoding::Utf8).is_err());
+
+        // Every directive is recognized, not only buttons and rules
+        let dos = b"\xFETITLE Acme\r\n03\xFETOC\r\n04One\r\n01---";
+        let contents = decode(dos, Encoding::Cp437)?;
+
+        assert_eq!(contents, "þTITLE Acme\r\n03þTOC\r\n04One\r\n01---");
+        assert_eq!(
+            branding::find_branding(&contents).title.as_deref(),
+            Some("Acme")
+        );
+        assert!(matches!(
+            &parse_document(&contents).0[0].nodes[1],
+            Node::Button(button) if button.label.as_deref() == Some("One")
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use std::{env, fs, path::PathBuf};
+
+const MAX_ENTRIES: usize = 100;
+
+// Searches from this and earlier sessions, oldest first
+#[derive(Default)]
+pub struct History {
+    pub entries: Vec<String>,
+    path: Option<PathBuf>,
+}
+
+impl History {
+    // Load the saved history. A missing or unreadable file starts a new one.
+    pub fn load() -> Self {
+        let path = history_path();
+
+        let entries = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| contents.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+
+        Self { entries, path }
+    }
+
+    // Add a search to the end of the history and save it. Failing to save only
+    // loses the history, so errors are ignored.
+    pub fn add(&mut self, search: &str) {
+        self.entries.retain(|entry| entry != search);
+        self.entries.push(search.to_string());
+
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.drain(..self.entries.len() - MAX_ENTRIES);
+        }
+
+        if let Some(path) = &self.path {
+            if let Some(parent) = path.parent() {
+                let _ = fs::create_dir_all(parent);
+            }
+
+            let _ = fs::write(path, self.entries.join("\n") + "\n");
+        }
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    let state_dir = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+    };
+
+    Some(state_dir.join("catgen/search_history"))
+}
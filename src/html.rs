@@ -0,0 +1,140 @@
+use std::fmt::Write;
+
+use crate::{
+    ansi::VGA_PALETTE,
+    ast::{Line, Node, Span},
+    parser::{button_spans, layout_line, DEFAULT_RULE},
+};
+
+const EXPORT_WIDTH: u16 = 80;
+
+// Render the document as a self contained HTML page. Each line gets an `L<n>`
+// anchor that buttons link to.
+pub fn document_to_html(document: &[Line], title: &str, blink: bool) -> String {
+    let mut body = String::new();
+
+    for (i, line) in document.iter().enumerate() {
+        write!(body, "<span id=\"L{}\">", i).unwrap();
+
+        if line.rule().is_some() {
+            body.push_str(&spans_to_html(&layout_line(
+                line,
+                EXPORT_WIDTH,
+                DEFAULT_RULE,
+            )));
+        } else {
+            for node in &line.nodes {
+                match node {
+                    Node::Span(span) => body.push_str(&spans_to_html(std::slice::from_ref(span))),
+                    Node::Button(button) => write!(
+                        body,
+                        "<a href=\"#L{}\">{}</a>",
+                        button.target,
+                        spans_to_html(&button_spans(button))
+                    )
+                    .unwrap(),
+                    Node::Rule(_) => {}
+                }
+            }
+        }
+
+        body.push_str("</span>\n");
+    }
+
+    format!(
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         <head>\n\
+         <meta charset=\"utf-8\">\n\
+         <title>{}</title>\n\
+         <style>\n{}</style>\n\
+         </head>\n\
+         <body>\n\
+         <pre>\n{}</pre>\n\
+         </body>\n\
+         </html>\n",
+        escape(title),
+        stylesheet(blink),
+        body
+    )
+}
+
+fn spans_to_html(spans: &[Span]) -> String {
+    let mut result = String::new();
+
+    for span in spans.iter().filter(|span| !span.text.is_empty()) {
+        let attributes = &span.attributes;
+
+        write!(
+            result,
+            "<span class=\"f{} b{}{}\">{}</span>",
+            attributes.foreground,
+            attributes.background,
+            if attributes.blink { " blink" } else { "" },
+            escape(&span.text)
+        )
+        .unwrap();
+    }
+
+    result
+}
+
+fn stylesheet(blink: bool) -> String {
+    let mut css = String::from(
+        "body { margin: 0; background: #000; }\n\
+         pre { margin: 0; padding: 1em; color: #AAAAAA; background: #000; \
+         font-family: \"Perfect DOS VGA 437\", \"Courier New\", monospace; }\n\
+         a { text-decoration: none; }\n\
+         a:hover span { text-decoration: underline; }\n",
+    );
+
+    for (i, (r, g, b)) in VGA_PALETTE.iter().enumerate() {
+        writeln!(css, ".f{} {{ color: #{:02X}{:02X}{:02X}; }}", i, r, g, b).unwrap();
+    }
+
+    // Only the first eight colors can be used as a background
+    for (i, (r, g, b)) in VGA_PALETTE.iter().take(8).enumerate() {
+        writeln!(
+            css,
+            ".b{} {{ background: #{:02X}{:02X}{:02X}; }}",
+            i, r, g, b
+        )
+        .unwrap();
+    }
+
+    if blink {
+        css.push_str(
+            ".blink { animation: blink 1s step-end infinite; }\n\
+             @keyframes blink { 50% { color: transparent; } }\n",
+        );
+    }
+
+    css
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_document;
+
+    #[test]
+    fn test_document_to_html() {
+        let (document, _) = parse_document("0F<Hello> ~12world\n03þBUTTON0000");
+        let html = document_to_html(&document, "Test", false);
+
+        assert!(html.contains(
+            "<span id=\"L0\"><span class=\"f15 b0\">&lt;Hello&gt; </span>\
+             <span class=\"f2 b0 blink\">world</span></span>"
+        ));
+        assert!(html.contains("<span id=\"L1\"><a href=\"#L0\">"));
+        assert!(html.contains(".f6 { color: #AA5500; }"));
+        assert!(!html.contains("@keyframes"));
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::BTreeMap;
+
+use crate::ansi::DOS_COLORS;
+use crate::ast::Attributes;
+use crate::parser::DEFAULT_ATTRIBUTES;
+
+// ANSI art is drawn for an 80 column screen and wraps at the edge
+const SCREEN_WIDTH: usize = 80;
+const SCREEN_HEIGHT: usize = 25;
+
+type Cell = (char, Attributes);
+
+// Convert decoded ANSI art into catalog markup. Returns the markup and a
+// warning for each kind of escape sequence that has no markup equivalent.
+pub fn ansi_to_markup(text: &str) -> (String, Vec<String>) {
+    let mut terminal = Terminal::default();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            // End of file, followed by the SAUCE metadata record
+            '\x1A' => break,
+            '\x1B' if chars.peek() == Some(&'[') => {
+                chars.next();
+
+                let mut params = String::new();
+                let mut command = None;
+
+                for c in chars.by_ref() {
+                    if c.is_ascii_digit() || c == ';' || c == '?' {
+                        params.push(c);
+                    } else {
+                        command = Some(c);
+                        break;
+                    }
+                }
+
+                if let Some(command) = command {
+                    terminal.control(command, &params);
+                }
+            }
+            '\x1B' => terminal.warn("escape sequences other than CSI"),
+            '\r' => terminal.column = 0,
+            '\n' => {
+                terminal.row += 1;
+                terminal.column = 0;
+            }
+            '\t' => terminal.column = (terminal.column / 8 + 1) * 8,
+            '~' => {
+                // The markup has no escape for its color code marker
+                terminal.warn("'~' characters (replaced with '˜')");
+                terminal.put('˜');
+            }
+            c => terminal.put(c),
+        }
+    }
+
+    (terminal.to_markup(), terminal.warnings())
+}
+
+// A virtual screen the ANSI art is drawn onto
+#[derive(Default)]
+struct Terminal {
+    rows: Vec<Vec<Cell>>,
+    row: usize,
+    column: usize,
+    saved: (usize, usize),
+    attributes: Option<Attributes>,
+    bold: bool,
+    unsupported: BTreeMap<String, usize>,
+}
+
+impl Terminal {
+    fn attributes(&self) -> Attributes {
+        let mut attributes = self.attributes.unwrap_or(DEFAULT_ATTRIBUTES);
+
+        if self.bold {
+            attributes.foreground |= 8;
+        }
+
+        attributes
+    }
+
+    fn put(&mut self, c: char) {
+        if self.column >= SCREEN_WIDTH {
+            self.row += 1;
+            self.column = 0;
+        }
+
+        let attributes = self.attributes();
+        let blank = (' ', DEFAULT_ATTRIBUTES);
+
+        if self.rows.len() <= self.row {
+            self.rows.resize(self.row + 1, Vec::new());
+        }
+
+        let row = &mut self.rows[self.row];
+        if row.len() <= self.column {
+            row.resize(self.column + 1, blank);
+        }
+
+        row[self.column] = (c, attributes);
+        self.column += 1;
+    }
+
+    fn control(&mut self, command: char, params: &str) {
+        let numbers = params
+            .split(';')
+            .map(|param| param.trim_start_matches('?').parse::<usize>().ok())
+            .collect::<Vec<Option<usize>>>();
+        let count = numbers.first().copied().flatten().unwrap_or(1).max(1);
+
+        match command {
+            'm' => self.select_graphic_rendition(&numbers),
+            'A' => self.row = self.row.saturating_sub(count),
+            'B' => self.move_to_row(self.row.saturating_add(count)),
+            'C' => self.column = (self.column + count).min(SCREEN_WIDTH - 1),
+            'D' => self.column = self.column.saturating_sub(count),
+            'H' | 'f' => {
+                self.move_to_row(numbers.first().copied().flatten().unwrap_or(1).max(1) - 1);
+                self.column = numbers.get(1).copied().flatten().unwrap_or(1).max(1) - 1;
+            }
+            'J' if numbers.first() == Some(&Some(2)) => {
+                self.rows.clear();
+                self.row = 0;
+                self.column = 0;
+            }
+            'K' => {
+                if let Some(row) = self.rows.get_mut(self.row) {
+                    row.truncate(self.column);
+                }
+            }
+            's' => self.saved = (self.row, self.column),
+            'u' => (self.row, self.column) = self.saved,
+            // Show and hide the cursor, and other modes that do not draw
+            'h' | 'l' => {}
+            _ => self.warn(&format!("escape sequence '{}'", command)),
+        }
+    }
+
+    // Cursor movement may go at most a screen past the drawing, so a huge row
+    // number cannot make the screen allocate every row up to it
+    fn move_to_row(&mut self, row: usize) {
+        let limit = self.rows.len() + SCREEN_HEIGHT;
+
+        if row > limit {
+            self.warn("cursor movement far below the drawing (moved one screen down)");
+        }
+
+        self.row = row.min(limit);
+    }
+
+    fn select_graphic_rendition(&mut self, numbers: &[Option<usize>]) {
+        let mut attributes = self.attributes.unwrap_or(DEFAULT_ATTRIBUTES);
+        let mut codes = numbers.iter().map(|code| code.unwrap_or(0));
+
+        while let Some(code) = codes.next() {
+            match code {
+                0 => {
+                    attributes = DEFAULT_ATTRIBUTES;
+                    self.bold = false;
+                }
+                1 => self.bold = true,
+                22 => self.bold = false,
+                5 | 6 => attributes.blink = true,
+                25 => attributes.blink = false,
+                30..=37 => attributes.foreground = DOS_COLORS[code - 30],
+                39 => attributes.foreground = DEFAULT_ATTRIBUTES.foreground,
+                40..=47 => attributes.background = DOS_COLORS[code - 40],
+                49 => attributes.background = DEFAULT_ATTRIBUTES.background,
+                90..=97 => attributes.foreground = DOS_COLORS[code - 90] | 8,
+                100..=107 => {
+                    self.warn("bright backgrounds (using the normal color)");
+                    attributes.background = DOS_COLORS[code - 100];
+                }
+                38 | 48 => {
+                    // Skip the color so its numbers are not read as codes
+                    match codes.next() {
+                        Some(5) => {
+                            codes.next();
+                            self.warn("256 colors");
+                        }
+                        Some(2) => {
+                            codes.by_ref().take(3).for_each(drop);
+                            self.warn("24-bit colors");
+                        }
+                        _ => self.warn("extended colors"),
+                    }
+                }
+                3 => self.warn("italic"),
+                4 => self.warn("underline"),
+                7 => self.warn("reverse video"),
+                8 => self.warn("hidden text"),
+                9 => self.warn("strikethrough"),
+                code => self.warn(&format!("graphic rendition {}", code)),
+            }
+        }
+
+        self.attributes = Some(attributes);
+    }
+
+    fn warn(&mut self, feature: &str) {
+        *self.unsupported.entry(feature.to_string()).or_default() += 1;
+    }
+
+    fn warnings(&self) -> Vec<String> {
+        self.unsupported
+            .iter()
+            .map(|(feature, count)| match count {
+                1 => format!("{} cannot be represented (used once)", feature),
+                count => format!("{} cannot be represented (used {} times)", feature, count),
+            })
+            .collect()
+    }
+
+    // Every line starts with its color code and adds one whenever it changes
+    fn to_markup(&self) -> String {
+        let mut markup = String::new();
+
+        for row in &self.rows {
+            // Trailing spaces on a black background are invisible
+            let length = row
+                .iter()
+                .rposition(|(c, attributes)| *c != ' ' || attributes.background != 0)
+                .map_or(0, |last| last + 1);
+
+            let mut current = None;
+
+            for (c, attributes) in &row[..length] {
+                if current != Some(*attributes) {
+                    if current.is_some() {
+                        markup.push('~');
+                    }
+
+                    markup.push_str(&color_code(attributes));
+                    current = Some(*attributes);
+                }
+
+                markup.push(*c);
+            }
+
+            if current.is_none() {
+                markup.push_str(&color_code(&DEFAULT_ATTRIBUTES));
+            }
+
+            markup.push('\n');
+        }
+
+        markup
+    }
+}
+
+fn color_code(attributes: &Attributes) -> String {
+    format!(
+        "{:X}{:X}",
+        attributes.background * 2 + attributes.blink as u8,
+        attributes.foreground
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ansi_to_markup() {
+        let (markup, warnings) =
+            ansi_to_markup("\x1B[0;1;34mHi \x1B[0;31;47mthere\x1B[0m\r\n\n\x1B[5CX█\x1B[4m!");
+
+        assert_eq!(markup, "09Hi ~E4there\n07\n07     X█!\n");
+        assert_eq!(
+            warnings,
+            vec!["underline cannot be represented (used once)"]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_colors() {
+        let (markup, warnings) = ansi_to_markup("\x1B[38;5;196mA\x1B[38;2;1;2;3mB\x1B[101mC");
+
+        assert_eq!(markup, "07AB~87C\n");
+        assert_eq!(
+            warnings,
+            vec![
+                "24-bit colors cannot be represented (used once)",
+                "256 colors cannot be represented (used once)",
+                "bright backgrounds (using the normal color) cannot be represented (used once)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_far_cursor_movement() {
+        let (markup, warnings) = ansi_to_markup("A\x1B[4000000000;1HX\x1B[4000000000BY");
+
+        let lines = markup.lines().collect::<Vec<&str>>();
+
+        assert_eq!(lines.len(), 3 + SCREEN_HEIGHT * 2);
+        assert_eq!(lines[SCREEN_HEIGHT + 1], "07X");
+        assert_eq!(lines.last(), Some(&"07 Y"));
+        assert_eq!(
+            warnings,
+            vec![
+                "cursor movement far below the drawing (moved one screen down) cannot be represented (used 2 times)"
+            ]
+        );
+    }
+}
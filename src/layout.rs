@@ -0,0 +1,211 @@
+use std::ops::Range;
+
+use crate::{
+    ansi::{spans_to_ansi, ColorDepth},
+    ast::{Attributes, Line, Span},
+    parser::{button_columns, layout_line, RuleColors},
+};
+
+// A screen row of the document. Long lines wrap onto several rows.
+pub struct Row {
+    // The document line and the visible column of it the row starts at
+    pub line: usize,
+    pub column: usize,
+    pub spans: Vec<Span>,
+    // The spans compiled to ANSI escape codes
+    pub text: String,
+    // The columns of the row each button covers, with its target line
+    pub buttons: Vec<(Range<usize>, usize)>,
+}
+
+impl Row {
+    // The target of the button drawn at a column of the row
+    pub fn button_at(&self, column: usize) -> Option<usize> {
+        self.buttons
+            .iter()
+            .find(|(columns, _)| columns.contains(&column))
+            .map(|(_, target)| *target)
+    }
+}
+
+// The document wrapped to the width of the screen
+pub struct Layout {
+    pub rows: Vec<Row>,
+    first_rows: Vec<usize>,
+}
+
+impl Layout {
+    pub fn new(document: &[Line], width: u16, rule: RuleColors, depth: ColorDepth) -> Self {
+        let mut rows = Vec::new();
+        let mut first_rows = Vec::new();
+
+        for (i, line) in document.iter().enumerate() {
+            first_rows.push(rows.len());
+            let buttons = button_columns(line);
+
+            for (column, spans) in wrap_spans(layout_line(line, width, rule), width as usize) {
+                let length = spans
+                    .iter()
+                    .map(|span| span.text.chars().count())
+                    .sum::<usize>();
+
+                // Buttons that wrap are split between the rows they cover
+                let buttons = buttons
+                    .iter()
+                    .map(|(columns, target)| {
+                        let start = columns.start.max(column) - column;
+                        let end = columns.end.min(column + length).saturating_sub(column);
+                        (start..end, *target)
+                    })
+                    .filter(|(columns, _)| !columns.is_empty())
+                    .collect();
+
+                rows.push(Row {
+                    line: i,
+                    column,
+                    text: spans_to_ansi(&spans, depth),
+                    spans,
+                    buttons,
+                });
+            }
+        }
+
+        Self { rows, first_rows }
+    }
+
+    // The document line and column shown at the start of a row
+    pub fn position(&self, row: usize) -> (usize, usize) {
+        self.rows
+            .get(row)
+            .map_or((0, 0), |row| (row.line, row.column))
+    }
+
+    // The row showing the given column of a document line
+    pub fn row_of(&self, line: usize, column: usize) -> usize {
+        let Some(&first) = self.first_rows.get(line) else {
+            return self.rows.len().saturating_sub(1);
+        };
+
+        self.rows[first..]
+            .iter()
+            .take_while(|row| row.line == line)
+            .filter(|row| row.column <= column)
+            .count()
+            .saturating_sub(1)
+            + first
+    }
+}
+
+// Split spans into rows no wider than `width`, breaking at spaces where
+// possible. Returns each row with the column it starts at.
+fn wrap_spans(spans: Vec<Span>, width: usize) -> Vec<(usize, Vec<Span>)> {
+    let chars = spans
+        .iter()
+        .flat_map(|span| span.text.chars().map(|c| (c, span.attributes)))
+        .collect::<Vec<(char, Attributes)>>();
+
+    // Lines that fit are kept as they are, including any empty spans
+    if chars.len() <= width || width == 0 {
+        return vec![(0, spans)];
+    }
+
+    let mut rows = Vec::new();
+    let mut start = 0;
+
+    while start < chars.len() {
+        let mut end = (start + width).min(chars.len());
+        let mut next = end;
+
+        if end < chars.len() {
+            // Break after the last space that fits, or mid word if there is none
+            if let Some(space) = (start + 1..=end).rev().find(|&i| chars[i].0 == ' ') {
+                end = space;
+                next = space + 1;
+            }
+        }
+
+        rows.push((start, group_spans(&chars[start..end])));
+        start = next;
+    }
+
+    rows
+}
+
+// Join characters back into spans of the same color
+fn group_spans(chars: &[(char, Attributes)]) -> Vec<Span> {
+    let mut spans: Vec<Span> = Vec::new();
+
+    for (c, attributes) in chars {
+        match spans.last_mut() {
+            Some(span) if span.attributes == *attributes => span.text.push(*c),
+            _ => spans.push(Span {
+                attributes: *attributes,
+                text: c.to_string(),
+            }),
+        }
+    }
+
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_document, DEFAULT_RULE};
+
+    fn rows(document: &str, width: u16) -> Vec<(usize, usize, String)> {
+        let (lines, _) = parse_document(document);
+
+        Layout::new(&lines, width, DEFAULT_RULE, ColorDepth::Basic)
+            .rows
+            .iter()
+            .map(|row| {
+                let text = row.spans.iter().map(|span| span.text.as_str()).collect();
+                (row.line, row.column, text)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_wrap_at_word_boundaries() {
+        assert_eq!(
+            rows("0Fthe quick ~04brown fox\n03short", 12),
+            vec![
+                (0, 0, "the quick".to_string()),
+                (0, 10, "brown fox".to_string()),
+                (1, 0, "short".to_string()),
+            ]
+        );
+        assert_eq!(
+            rows("0Fabcdefghij", 4),
+            vec![
+                (0, 0, "abcd".to_string()),
+                (0, 4, "efgh".to_string()),
+                (0, 8, "ij".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wrap_keeps_colors() {
+        let (lines, _) = parse_document("0Fthe quick ~04brown fox");
+        let layout = Layout::new(&lines, 12, DEFAULT_RULE, ColorDepth::Basic);
+
+        assert_eq!(layout.rows[1].spans[0].attributes.foreground, 4);
+        assert_eq!(layout.row_of(0, 12), 1);
+        assert_eq!(layout.position(1), (0, 10));
+    }
+
+    #[test]
+    fn test_buttons_by_column() {
+        let (lines, _) = parse_document("03þBUTTON0001[One] or þBUTTON0000[Two]");
+        let layout = Layout::new(&lines, 20, DEFAULT_RULE, ColorDepth::Basic);
+
+        // The second button wraps at the space before its label
+        assert_eq!(layout.rows[0].buttons, vec![(0..11, 1), (15..18, 0)]);
+        assert_eq!(layout.rows[1].buttons, vec![(0..7, 0)]);
+        assert_eq!(layout.rows[0].button_at(5), Some(1));
+        assert_eq!(layout.rows[0].button_at(12), None);
+        assert_eq!(layout.rows[1].button_at(3), Some(0));
+    }
+}
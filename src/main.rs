@@ -1,28 +1,190 @@
 mod ansi;
+mod ast;
+mod branding;
+mod bundle;
+mod cli;
+mod cp437;
+mod document;
+mod history;
+mod html;
+mod import;
+mod layout;
 mod parser;
+mod pdf;
+mod print;
+mod prompt;
 mod search;
 mod state;
+mod theme;
 mod ui;
 
-use anyhow::Result;
-use parser::PrintableLine;
-use state::State;
-use std::io::{stdin, stdout, BufRead, Stdout, Write};
+use anyhow::{Context, Result};
+use ast::{Heading, Line};
+use history::History;
+use layout::{Layout, Row};
+use prompt::{Prompt, PromptAction};
+use state::{ApplicationState, State};
+use std::{
+    io::{stdout, ErrorKind, IsTerminal, Stdout, Write},
+    ops::Range,
+};
 use ui::{draw_footer, draw_scollbar, draw_search};
 
 use crossterm::{
     cursor::{self, MoveTo},
     event::{
         DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind,
-        MouseButton, MouseEvent, MouseEventKind,
+        KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
     },
-    style::Print,
+    style::{Print, ResetColor},
     terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand, QueueableCommand,
 };
 
 fn main() -> Result<()> {
-    let mut state = State::new(terminal::size()?, DATA.lines().count());
+    let (args, options) = cli::take_global_options(std::env::args().skip(1))?;
+    let encoding = options.encoding;
+    let theme = theme::load(options.theme.as_deref())?;
+
+    match cli::parse_args(args.into_iter())? {
+        cli::Command::View(source) => {
+            let document = document::load(&source, DATA, encoding)?;
+            run_viewer(&document, theme)
+        }
+        cli::Command::Check(source) => {
+            let document = document::load(&source, DATA, encoding)?;
+            check(&source, &document)
+        }
+        cli::Command::Render {
+            source,
+            width,
+            color,
+        } => {
+            let (document, _) = parser::parse_document(&document::load(&source, DATA, encoding)?);
+            render(&document, width, color, theme.rule_colors())
+        }
+        cli::Command::Build {
+            source,
+            output,
+            stub,
+        } => {
+            let document = document::load(&source, DATA, encoding)?;
+
+            // Broken buttons would only show up once the catalog is shipped
+            report_problems(&source, &document)?;
+
+            let stub = match stub {
+                Some(stub) => stub,
+                None => std::env::current_exe()?,
+            };
+
+            bundle::build(&stub, &document, &output)
+        }
+        cli::Command::Export {
+            format,
+            source,
+            output,
+        } => {
+            let contents = document::load(&source, DATA, encoding)?;
+            let (document, _) = parser::parse_document(&contents);
+
+            // Title the export after the catalog, or its file name without one
+            let title = branding::find_branding(&contents)
+                .title
+                .unwrap_or_else(|| source.to_string());
+
+            let contents = match format {
+                cli::ExportFormat::Html { blink } => {
+                    html::document_to_html(&document, &title, blink).into_bytes()
+                }
+                cli::ExportFormat::Pdf => pdf::document_to_pdf(&document, &title),
+            };
+
+            match output {
+                Some(output) => std::fs::write(&output, contents)
+                    .with_context(|| format!("Unable to write {}", output.display())),
+                None => Ok(stdout().write_all(&contents)?),
+            }
+        }
+        cli::Command::Import { source, output } => {
+            let (markup, warnings) = import::ansi_to_markup(&document::decode(
+                &document::load_bytes(&source)?,
+                encoding,
+            )?);
+
+            for warning in &warnings {
+                eprintln!("{}: warning: {}", source, warning);
+            }
+
+            match output {
+                Some(output) => std::fs::write(&output, markup)
+                    .with_context(|| format!("Unable to write {}", output.display())),
+                None => Ok(stdout().write_all(markup.as_bytes())?),
+            }
+        }
+    }
+}
+
+fn check(source: &cli::Source, document: &str) -> Result<()> {
+    report_problems(source, document)?;
+
+    println!("{}: no problems found", source);
+    Ok(())
+}
+
+// Report every problem in the document, compiler style, failing if any
+fn report_problems(source: &cli::Source, document: &str) -> Result<()> {
+    let (_, diagnostics) = parser::parse_document(document);
+
+    for diagnostic in &diagnostics {
+        eprintln!("{}:{}", source, diagnostic);
+    }
+
+    match diagnostics.len() {
+        0 => Ok(()),
+        1 => anyhow::bail!("1 problem found"),
+        count => anyhow::bail!("{} problems found", count),
+    }
+}
+
+// Write the document to stdout without taking over the terminal
+fn render(
+    document: &[Line],
+    width: Option<u16>,
+    color: cli::ColorMode,
+    rule: parser::RuleColors,
+) -> Result<()> {
+    let is_terminal = stdout().is_terminal();
+
+    let width = match width {
+        Some(width) => width,
+        None if is_terminal => terminal::size().map_or(80, |(width, _)| width),
+        None => 80,
+    };
+
+    let color = match color {
+        cli::ColorMode::Always => true,
+        cli::ColorMode::Never => false,
+        cli::ColorMode::Auto => is_terminal,
+    };
+
+    match stdout().write_all(
+        ansi::document_to_ansi(document, width, color, rule, ansi::ColorDepth::detect()).as_bytes(),
+    ) {
+        // Stop quietly when piped into something like `head`
+        Err(err) if err.kind() == ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+fn run_viewer(contents: &str, theme: theme::Theme) -> Result<()> {
+    let (document, _) = parser::parse_document(contents);
+
+    let mut state = State::new(terminal::size()?, document.len());
+    state.search_history = History::load();
+    state.branding = branding::find_branding(contents);
+    state.theme = theme;
+    state.color_depth = ansi::ColorDepth::detect();
 
     stdout()
         .execute(EnterAlternateScreen)?
@@ -30,7 +192,13 @@ fn main() -> Result<()> {
 
     terminal::enable_raw_mode()?;
 
-    let lines = parser::compile_lines(DATA, state.width - 1);
+    let mut layout = Layout::new(
+        &document,
+        state.document_width(),
+        state.theme.rule_colors(),
+        state.color_depth,
+    );
+    state.document_length = layout.rows.len();
 
     let mut stdout = stdout();
 
@@ -38,17 +206,10 @@ fn main() -> Result<()> {
 
     while state.running {
         if state.is_dirty {
-            // Loop through all the lines that fit on the screen
-            draw_doc(&stdout, &lines, &state)?;
-            draw_scollbar(&stdout, &state)?;
-            draw_footer(&stdout, &state)?;
-
-            if state.application_state != state::ApplicationState::Normal {
-                ui::draw_search(&stdout, &state)?;
-            }
+            draw_screen(&stdout, &layout, &state)?;
         }
 
-        handle_events(&mut state, &lines)?;
+        handle_events(&mut state, &mut layout, &document)?;
     }
 
     terminal::disable_raw_mode()?;
@@ -58,16 +219,51 @@ fn main() -> Result<()> {
         .execute(LeaveAlternateScreen)?
         .execute(DisableMouseCapture)?;
 
-    ui::draw_closing_screen(&stdout)?;
+    ui::draw_closing_screen(&stdout, &state)?;
 
     Ok(())
 }
 
-fn handle_events(state: &mut State, lines: &Vec<PrintableLine>) -> Result<()> {
+fn handle_events(state: &mut State, layout: &mut Layout, document: &[Line]) -> Result<()> {
     let previous_line = state.current_line;
     let mut is_dirty = false;
 
-    match crossterm::event::read()? {
+    let event = crossterm::event::read()?;
+
+    // Only quitting and resizing work until the terminal is big enough
+    if state.is_too_small()
+        && !matches!(
+            event,
+            Event::Resize(..)
+                | Event::Key(KeyEvent {
+                    code: KeyCode::Esc,
+                    ..
+                })
+        )
+    {
+        return Ok(());
+    }
+
+    // Status messages last until the next key press
+    if let Event::Key(KeyEvent {
+        kind: KeyEventKind::Press,
+        ..
+    }) = event
+    {
+        is_dirty = state.status.take().is_some();
+    }
+
+    match event {
+        // Escape leaves search mode before it quits
+        Event::Key(KeyEvent {
+            code: KeyCode::Esc,
+            kind: KeyEventKind::Press,
+            ..
+        }) if state.application_state != ApplicationState::Normal => {
+            state.application_state = ApplicationState::Normal;
+            is_dirty = true;
+        }
+
         Event::Key(KeyEvent {
             code: KeyCode::Esc,
             kind: KeyEventKind::Press,
@@ -75,6 +271,61 @@ fn handle_events(state: &mut State, lines: &Vec<PrintableLine>) -> Result<()> {
             modifiers: _,
         }) => state.running = false,
 
+        Event::Key(KeyEvent {
+            code: code @ (KeyCode::Tab | KeyCode::BackTab),
+            kind: KeyEventKind::Press,
+            ..
+        }) => {
+            state.cycle_focus(layout, code == KeyCode::Tab);
+            is_dirty = true;
+        }
+
+        Event::Key(KeyEvent {
+            code: KeyCode::Enter,
+            kind: KeyEventKind::Press,
+            ..
+        }) => {
+            if let Some(target) = state.focused_button(layout) {
+                state.focus = None;
+                state.follow_link(layout, target);
+                is_dirty = true;
+            }
+        }
+
+        // Backspace or Alt-Left go back to where the last button was followed
+        Event::Key(KeyEvent {
+            code: KeyCode::Backspace,
+            kind: KeyEventKind::Press,
+            ..
+        })
+        | Event::Key(KeyEvent {
+            code: KeyCode::Left,
+            modifiers: KeyModifiers::ALT,
+            kind: KeyEventKind::Press,
+            ..
+        }) => {
+            let moved = state.go_back(layout);
+
+            if !moved {
+                state.status = Some("No earlier position to go back to".to_string());
+                is_dirty = true;
+            }
+        }
+
+        Event::Key(KeyEvent {
+            code: KeyCode::Right,
+            modifiers: KeyModifiers::ALT,
+            kind: KeyEventKind::Press,
+            ..
+        }) => {
+            let moved = state.go_forward(layout);
+
+            if !moved {
+                state.status = Some("No later position to go forward to".to_string());
+                is_dirty = true;
+            }
+        }
+
         Event::Key(KeyEvent {
             code: KeyCode::Up,
             kind: KeyEventKind::Press,
@@ -118,19 +369,92 @@ fn handle_events(state: &mut State, lines: &Vec<PrintableLine>) -> Result<()> {
             kind: KeyEventKind::Press,
             ..
         }) => {
-            terminal::disable_raw_mode()?;
-            let search = get_search_string(state)?.trim().to_string();
+            if let Some(search) = get_search_string(state, layout, document)? {
+                let search = search.trim().to_string();
 
-            // If the search string is empty, go back to normal mode
-            if search.is_empty() {
-                state.application_state = state::ApplicationState::Normal;
-            } else {
-                state.application_state = search::get_search_state(&lines, &search);
+                // If the search string is empty, go back to normal mode
+                if search.is_empty() {
+                    state.application_state = ApplicationState::Normal;
+                } else {
+                    state.search_history.add(&search);
+                    run_search(state, layout, document, &search);
+                }
+            }
+
+            is_dirty = true;
+        }
+
+        // Alt-C, Alt-W and Alt-R toggle ignoring case, whole words and regex
+        Event::Key(KeyEvent {
+            code: KeyCode::Char(toggle @ ('c' | 'w' | 'r')),
+            modifiers: KeyModifiers::ALT,
+            kind: KeyEventKind::Press,
+            ..
+        }) => {
+            state.search_options.toggle(toggle);
+
+            match &state.application_state {
+                ApplicationState::Search(results) => {
+                    let search = results.search.clone();
+                    run_search(state, layout, document, &search);
+                }
+                ApplicationState::Normal => {
+                    state.status = Some(match state.search_options.to_string() {
+                        options if options.is_empty() => "Exact search".to_string(),
+                        options => format!("Search{}", options),
+                    });
+                }
             }
 
             is_dirty = true;
         }
 
+        Event::Key(KeyEvent {
+            code: code @ (KeyCode::Char('n') | KeyCode::Char('N')),
+            kind: KeyEventKind::Press,
+            ..
+        }) => {
+            if let ApplicationState::Search(results) = &mut state.application_state {
+                if code == KeyCode::Char('n') {
+                    results.next();
+                } else {
+                    results.previous();
+                }
+
+                state.reveal_search_match(layout);
+                is_dirty = true;
+            }
+        }
+
+        Event::Key(KeyEvent {
+            code: KeyCode::Char('t'),
+            kind: KeyEventKind::Press,
+            ..
+        }) => {
+            let headings = parser::find_headings(document);
+
+            if headings.is_empty() {
+                state.status = Some("This document has no sections".to_string());
+            } else if let Some(heading) = show_outline(state, layout, document, &headings)? {
+                state.follow_link(layout, heading.line);
+            }
+
+            is_dirty = true;
+        }
+
+        Event::Key(KeyEvent {
+            code: KeyCode::Char('p' | 'P'),
+            kind: KeyEventKind::Press,
+            ..
+        }) => {
+            state.status = Some(match print::print_document(document) {
+                Ok(message) => message,
+                Err(err) => format!("Print failed: {}", err),
+            });
+
+            is_dirty = true;
+        }
+
         Event::Key(KeyEvent {
             code: KeyCode::PageUp,
             kind: KeyEventKind::Press,
@@ -142,25 +466,50 @@ fn handle_events(state: &mut State, lines: &Vec<PrintableLine>) -> Result<()> {
             row,
             column,
             ..
-        }) => {
-            if column < state.width - 1 {
-                // Find the document line that was clicked
-                let line = row as usize + state.current_line;
+        }) if (row as usize) < state.drawing_height() => {
+            let thumb = state.scrollbar_thumb();
+
+            if column < state.document_width() {
+                // Find the document row that was clicked
+                let row = row as usize + state.current_line;
 
-                if let Some(PrintableLine::Button(pos, _)) = lines.get(line) {
-                    state.scroll_to(*pos);
+                if let Some(target) = layout
+                    .rows
+                    .get(row)
+                    .and_then(|row| row.button_at(column as usize))
+                {
+                    state.follow_link(layout, target);
                 }
+            } else if row == 0 {
+                state.scroll_up(1);
+            } else if row as usize == state.drawing_height() - 1 {
+                state.scroll_down(1);
+            } else if thumb.contains(&row) {
+                state.scrollbar_drag = Some(row - thumb.start);
+            } else if row < thumb.start {
+                state.scroll_up(state.drawing_height());
             } else {
-                // If the click was above the scrollbar position, scroll up
-                if row < state.scrollbar_position() {
-                    state.scroll_up(state.drawing_height());
-                } else {
-                    state.scroll_down(state.drawing_height());
-                }
+                state.scroll_down(state.drawing_height());
             }
         }
+
+        // Dragging keeps the thumb under the mouse, even off the scrollbar
+        Event::Mouse(MouseEvent {
+            kind: MouseEventKind::Drag(MouseButton::Left),
+            row,
+            ..
+        }) => {
+            if let Some(grab) = state.scrollbar_drag {
+                state.drag_scrollbar(row.saturating_sub(grab));
+            }
+        }
+
+        Event::Mouse(MouseEvent {
+            kind: MouseEventKind::Up(MouseButton::Left),
+            ..
+        }) => state.scrollbar_drag = None,
         Event::Resize(width, height) => {
-            state.resize(width, height);
+            resize(state, layout, document, width, height);
             is_dirty = true;
         }
 
@@ -175,32 +524,195 @@ fn handle_events(state: &mut State, lines: &Vec<PrintableLine>) -> Result<()> {
     Ok(())
 }
 
-fn get_search_string(state: &mut State) -> Result<String, anyhow::Error> {
-    let mut line = String::new();
+// Wrap the document to the new size, keeping the same line at the top
+fn resize(state: &mut State, layout: &mut Layout, document: &[Line], width: u16, height: u16) {
+    let (line, column) = layout.position(state.current_line);
 
-    terminal::disable_raw_mode()?;
-    draw_search(&stdout(), state)?;
-    stdin().lock().read_line(&mut line).unwrap();
-    terminal::enable_raw_mode()?;
+    state.resize(width, height);
+    *layout = Layout::new(
+        document,
+        state.document_width(),
+        state.theme.rule_colors(),
+        state.color_depth,
+    );
+    state.document_length = layout.rows.len();
+    state.scroll_to(layout.row_of(line, column));
+}
+
+fn run_search(state: &mut State, layout: &Layout, document: &[Line], search: &str) {
+    match search::get_search_state(
+        document,
+        state.document_width(),
+        search,
+        state.search_options,
+        layout.position(state.current_line).0,
+    ) {
+        Ok(search_state) => {
+            state.application_state = search_state;
+            state.reveal_search_match(layout);
+        }
+        Err(err) => {
+            state.application_state = ApplicationState::Normal;
+            state.status = Some(err.to_string());
+        }
+    }
+}
+
+// Read a search string without leaving raw mode. Returns `None` if the search
+// was cancelled.
+fn get_search_string(
+    state: &mut State,
+    layout: &mut Layout,
+    document: &[Line],
+) -> Result<Option<String>> {
+    let mut prompt = Prompt::default();
+    let mut stdout = stdout();
+
+    stdout.execute(cursor::Show)?;
+
+    let search = loop {
+        draw_search(&stdout, state, &prompt)?;
+
+        match crossterm::event::read()? {
+            Event::Key(KeyEvent {
+                code: KeyCode::Char(toggle @ ('c' | 'w' | 'r')),
+                modifiers: KeyModifiers::ALT,
+                kind: KeyEventKind::Press,
+                ..
+            }) => state.search_options.toggle(toggle),
+
+            Event::Key(key) if key.kind == KeyEventKind::Press => {
+                match prompt.handle_key(key, &state.search_history.entries) {
+                    PromptAction::Continue => {}
+                    PromptAction::Submit(search) => break Some(search),
+                    PromptAction::Cancel => break None,
+                }
+            }
+
+            // Reflow and redraw the document behind the prompt, as the main
+            // loop would
+            Event::Resize(width, height) => {
+                resize(state, layout, document, width, height);
+                draw_screen(&stdout, layout, state)?;
 
-    Ok(line)
+                if state.is_too_small() {
+                    break None;
+                }
+            }
+
+            _ => {}
+        }
+    };
+
+    stdout.execute(cursor::Hide)?;
+
+    Ok(search)
 }
 
-fn draw_doc(
-    mut stdout: &Stdout,
-    lines: &Vec<PrintableLine>,
-    state: &State,
-) -> Result<(), anyhow::Error> {
-    for y in 0..state.drawing_height() {
-        if state.current_line + y as usize >= lines.len() {
-            break;
+// Let the user pick a section from a pop-up outline of the document
+fn show_outline<'a>(
+    state: &mut State,
+    layout: &mut Layout,
+    document: &[Line],
+    headings: &'a [Heading],
+) -> Result<Option<&'a Heading>> {
+    let stdout = stdout();
+
+    // Start at the section the top of the screen is in
+    let (line, _) = layout.position(state.current_line);
+    let mut selected = headings
+        .iter()
+        .rposition(|heading| heading.line <= line)
+        .unwrap_or(0);
+
+    loop {
+        ui::draw_outline(&stdout, state, headings, selected)?;
+
+        let page = state.drawing_height().saturating_sub(3).max(1);
+        let last = headings.len() - 1;
+
+        match crossterm::event::read()? {
+            Event::Key(KeyEvent {
+                code,
+                kind: KeyEventKind::Press,
+                ..
+            }) => match code {
+                KeyCode::Up => selected = selected.saturating_sub(1),
+                KeyCode::Down => selected = (selected + 1).min(last),
+                KeyCode::PageUp => selected = selected.saturating_sub(page),
+                KeyCode::PageDown => selected = (selected + page).min(last),
+                KeyCode::Home => selected = 0,
+                KeyCode::End => selected = last,
+                KeyCode::Enter => return Ok(Some(&headings[selected])),
+                KeyCode::Esc | KeyCode::Char('t') => return Ok(None),
+                _ => {}
+            },
+
+            Event::Resize(width, height) => {
+                resize(state, layout, document, width, height);
+                draw_screen(&stdout, layout, state)?;
+
+                if state.is_too_small() {
+                    return Ok(None);
+                }
+            }
+
+            _ => {}
         }
+    }
+}
+
+fn draw_screen(stdout: &Stdout, layout: &Layout, state: &State) -> Result<()> {
+    if state.is_too_small() {
+        return ui::draw_too_small(stdout, state);
+    }
+
+    // Loop through all the lines that fit on the screen
+    draw_doc(stdout, layout, state)?;
+    draw_scollbar(stdout, state)?;
+    draw_footer(stdout, state)?;
+
+    Ok(())
+}
+
+fn draw_doc(mut stdout: &Stdout, layout: &Layout, state: &State) -> Result<(), anyhow::Error> {
+    for y in 0..state.drawing_height() {
+        let Some(row) = layout.rows.get(state.current_line + y) else {
+            // Blank the rows below the end of the document, which may still
+            // show an outline or a longer layout from before
+            stdout
+                .queue(MoveTo(0, y as u16))?
+                .queue(ResetColor)?
+                .queue(Clear(ClearType::UntilNewLine))?;
+            continue;
+        };
 
         // Move the cursor to the start of the line
         stdout.queue(MoveTo(0, y as u16))?;
 
-        // Print the line
-        print_line(stdout, &lines[state.current_line + y as usize])?;
+        let mut ranges = search_ranges(state, row).unwrap_or_default();
+
+        // The focused button is highlighted the same way as a search match
+        if let Some((_, i)) = state
+            .focus
+            .filter(|(focus, _)| *focus == state.current_line + y)
+        {
+            ranges.extend(row.buttons.get(i).map(|(columns, _)| columns.clone()));
+        }
+
+        // Print the line, highlighting any search matches on it
+        match (!ranges.is_empty()).then_some(ranges) {
+            Some(ranges) => {
+                stdout
+                    .queue(Print(ansi::highlight_spans(
+                        &row.spans,
+                        &ranges,
+                        state.color_depth,
+                    )))?
+                    .queue(Clear(ClearType::UntilNewLine))?;
+            }
+            None => print_line(stdout, &row.text)?,
+        }
     }
 
     stdout.flush()?;
@@ -208,11 +720,27 @@ fn draw_doc(
     Ok(())
 }
 
-fn print_line(mut stdout: &Stdout, line: &PrintableLine) -> Result<()> {
-    let text = match line {
-        PrintableLine::Text(text) | PrintableLine::Button(_, text) => text,
+// The visible columns of the search matches on a row
+fn search_ranges(state: &State, row: &Row) -> Option<Vec<Range<usize>>> {
+    let ApplicationState::Search(results) = &state.application_state else {
+        return None;
     };
 
+    let ranges = results
+        .matches
+        .iter()
+        .filter(|found| found.line == row.line)
+        .map(|found| {
+            found.column.saturating_sub(row.column)
+                ..(found.column + found.length).saturating_sub(row.column)
+        })
+        .filter(|range| !range.is_empty())
+        .collect::<Vec<Range<usize>>>();
+
+    (!ranges.is_empty()).then_some(ranges)
+}
+
+fn print_line(mut stdout: &Stdout, text: &str) -> Result<()> {
     stdout
         .queue(Print(text))?
         .queue(Clear(ClearType::UntilNewLine))?;
@@ -1,61 +1,503 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+use regex::Regex;
+use std::{collections::HashMap, fmt, ops::Range, sync::LazyLock};
 
-use crate::ansi::color_to_ansi;
+use crate::ast::{Attributes, Button, Heading, Line, Node, Span};
 
-pub enum PrintableLine {
-    Button(usize, String),
-    Text(String),
+// The label of buttons that do not give their own
+const BUTTON_LABEL: &str = "Click Here";
+
+// Grey on black, used until a line sets a valid color
+pub(crate) const DEFAULT_ATTRIBUTES: Attributes = Attributes {
+    foreground: 7,
+    background: 0,
+    blink: false,
+};
+
+// Section headings are a red title over a blue underline of dashes
+const HEADING_ATTRIBUTES: Attributes = Attributes {
+    foreground: 4,
+    background: 0,
+    blink: false,
+};
+
+const UNDERLINE_ATTRIBUTES: Attributes = Attributes {
+    foreground: 1,
+    background: 0,
+    blink: false,
+};
+
+// A problem found in the markup, with a 1-based line and column
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: error: {}", self.line, self.column, self.message)
+    }
 }
 
-const BUTTON: &str = "~08>~07>~0F> Click Here <~07<~08<";
+// An error at a byte offset within a single line
+type LineError = (usize, String);
+
+// The names of the directives, each written after a þ marker
+const BUTTON: &str = "BUTTON";
+const ANCHOR: &str = "ANCHOR";
+const RULE: &str = "LINE";
+const TOC: &str = "TOC";
+const TITLE: &str = "TITLE";
+const LOGO: &str = "LOGO";
+const EXIT: &str = "EXIT";
+
+// Every directive, so DOS files writing the marker as byte 0xFE can be read
+pub const DIRECTIVES: [&str; 7] = [BUTTON, ANCHOR, RULE, TOC, TITLE, LOGO, EXIT];
+
+// Document settings, which are not shown as lines of the document
+const METADATA: [&str; 3] = [TITLE, LOGO, EXIT];
+
+const ANCHOR_NAME: &str = r"[A-Za-z_][\w-]*";
+
+// Metadata and þTOC start the line, after its color code, so prose can
+// mention them
+const LINE_START: &str = "^(?:[0-9A-Fa-f]{2})?";
+
+// Buttons jump to a four digit line number or to the name of an anchor, and
+// may be followed by a label in square brackets. Compiled once, as every line
+// of the document is matched against them.
+static DIRECTIVE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(
+        r"þ(?:{}(?:(?:(\d{{4}})| ({}))(?:\[([^\]~]*)\])?)?|{}(?: ({}))?)",
+        BUTTON, ANCHOR_NAME, ANCHOR, ANCHOR_NAME
+    ))
+    .unwrap()
+});
+static METADATA_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(r"{}þ({})(?: |$)", LINE_START, METADATA.join("|"))).unwrap()
+});
+static TABLE_OF_CONTENTS_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(&format!(r"{}þ{}\s*$", LINE_START, TOC)).unwrap());
+static BUTTON_LINE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(&format!(r"þ{}(\d{{4}})", BUTTON)).unwrap());
+static ANCHOR_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(&format!("þ{} ({})", ANCHOR, ANCHOR_NAME)).unwrap());
+
+// Anchor names and the line each one marks
+type Anchors = HashMap<String, usize>;
+
+// The colors of a rule, from its ends inwards
+pub type RuleColors = [u8; 3];
+
+pub const DEFAULT_RULE: RuleColors = [0x8, 0x7, 0xF];
+
+// Parse the whole document. Malformed markup is reported as diagnostics and
+// parsed as well as possible so the document can still be shown.
+pub fn parse_document(contents: &str) -> (Vec<Line>, Vec<Diagnostic>) {
+    let document_length = contents.lines().count();
+    let (anchors, mut diagnostics) = find_anchors(contents);
+
+    let mut lines = Vec::new();
+    let mut tables = Vec::new();
+    let mut hidden = Vec::new();
+
+    for (i, source) in contents.lines().enumerate() {
+        let (line, mut errors) = parse_line(source, &anchors);
 
-pub fn compile_lines(contents: &str, width: u16) -> Vec<PrintableLine> {
-    let lines = contents
-        .lines()
-        .map(|line| line_to_printable_line(line, width))
-        .collect::<Vec<PrintableLine>>();
-    lines
+        if metadata(source).is_some() {
+            hidden.push(i);
+        } else if TABLE_OF_CONTENTS_REGEX.is_match(source) {
+            tables.push(i);
+        }
+
+        for caps in BUTTON_LINE_REGEX.captures_iter(source) {
+            let target = caps[1].parse::<usize>().unwrap_or_default();
+
+            if target >= document_length {
+                errors.push((
+                    caps.get(1).unwrap().start(),
+                    format!(
+                        "Button target {} is past the end of the document ({} lines)",
+                        target, document_length
+                    ),
+                ));
+            }
+        }
+
+        errors.sort_by_key(|(offset, _)| *offset);
+
+        diagnostics.extend(errors.into_iter().map(|(offset, message)| Diagnostic {
+            line: i + 1,
+            column: source[..offset].chars().count() + 1,
+            message,
+        }));
+
+        lines.push(line);
+    }
+
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+
+    (expand_directives(lines, &tables, &hidden), diagnostics)
 }
 
-fn line_to_printable_line(line: &str, width: u16) -> PrintableLine {
-    if line.contains("þBUTTON") {
-        let button_regex = regex::Regex::new(r"þBUTTON(\d{4})").unwrap();
-        let caps = button_regex.captures(line).unwrap();
+// Replace each þTOC line with a button for every section heading and drop
+// metadata lines. Button targets are moved along with the lines after them.
+fn expand_directives(lines: Vec<Line>, tables: &[usize], hidden: &[usize]) -> Vec<Line> {
+    if tables.is_empty() && hidden.is_empty() {
+        return lines;
+    }
+
+    let headings = find_headings(&lines);
 
-        // Get the line number
-        let line_number = caps.get(1).unwrap().as_str().parse::<usize>().unwrap();
+    // Where each line ends up once the tables are expanded
+    let mut moved = Vec::with_capacity(lines.len());
+    let mut length = 0;
 
-        PrintableLine::Button(
-            line_number,
-            line_to_ansi(&button_regex.replace_all(line, BUTTON))
-                .unwrap()
-                .to_string(),
-        )
-    } else if line.contains("þLINE") {
-        PrintableLine::Text(generate_line(width, 1).unwrap())
-    } else {
-        PrintableLine::Text(line_to_ansi(line).unwrap().to_string())
+    for i in 0..lines.len() {
+        moved.push(length);
+        length += if tables.contains(&i) {
+            headings.len()
+        } else if hidden.contains(&i) {
+            0
+        } else {
+            1
+        };
     }
+
+    let move_target = |target: usize| moved.get(target).copied().unwrap_or(target);
+    let mut result = Vec::with_capacity(length);
+
+    for (i, mut line) in lines.into_iter().enumerate() {
+        if hidden.contains(&i) {
+            continue;
+        }
+
+        if !tables.contains(&i) {
+            for node in &mut line.nodes {
+                if let Node::Button(button) = node {
+                    button.target = move_target(button.target);
+                }
+            }
+
+            result.push(line);
+            continue;
+        }
+
+        for heading in &headings {
+            result.push(Line {
+                nodes: vec![
+                    Node::Span(Span {
+                        attributes: DEFAULT_ATTRIBUTES,
+                        text: "    ".to_string(),
+                    }),
+                    Node::Button(Button {
+                        target: move_target(heading.line),
+                        label: Some(heading.title.clone()),
+                        anchor: None,
+                    }),
+                ],
+            });
+        }
+    }
+
+    result
 }
 
-pub fn line_to_ansi(line: &str) -> Result<String> {
-    let mut result = String::new();
+// Collect every anchor before parsing so buttons can jump forward to them
+fn find_anchors(contents: &str) -> (Anchors, Vec<Diagnostic>) {
+    let mut anchors = Anchors::new();
+    let mut diagnostics = Vec::new();
+
+    for (i, source) in contents.lines().enumerate() {
+        for caps in ANCHOR_REGEX.captures_iter(source) {
+            let name = caps.get(1).unwrap();
+
+            match anchors.get(name.as_str()) {
+                Some(line) => diagnostics.push(Diagnostic {
+                    line: i + 1,
+                    column: source[..name.start()].chars().count() + 1,
+                    message: format!(
+                        "Anchor '{}' is already defined on line {}",
+                        name.as_str(),
+                        line + 1
+                    ),
+                }),
+                None => {
+                    anchors.insert(name.as_str().to_string(), i);
+                }
+            }
+        }
+    }
+
+    (anchors, diagnostics)
+}
+
+// A metadata directive on a line, with its value and the byte offset of it
+pub fn metadata(line: &str) -> Option<(&str, &str, usize)> {
+    let caps = METADATA_REGEX.captures(line)?;
+    let end = caps.get(0).unwrap().end();
+
+    Some((caps.get(1).unwrap().as_str(), &line[end..], end))
+}
+
+fn parse_line(line: &str, anchors: &Anchors) -> (Line, Vec<LineError>) {
+    let mut errors = Vec::new();
+
+    // Metadata is not part of the document, but its color markup is checked
+    if let Some((name, value, offset)) = metadata(line) {
+        if name != "TITLE" {
+            let mut markup_errors = Vec::new();
+            scan_spans(value, &mut markup_errors);
+
+            errors.extend(
+                markup_errors
+                    .into_iter()
+                    .map(|(position, message)| (offset + position, message)),
+            );
+        }
+
+        return (Line::default(), errors);
+    }
+
+    // Filled in with the table of contents once every heading is known
+    if TABLE_OF_CONTENTS_REGEX.is_match(line) {
+        return (Line::default(), errors);
+    }
+
+    if let Some(pos) = line.find(&format!("þ{}", RULE)) {
+        let kind = line[pos + "þ".len() + RULE.len()..]
+            .chars()
+            .next()
+            .and_then(|c| c.to_digit(10))
+            .unwrap_or(1);
+
+        let line = Line {
+            nodes: vec![Node::Rule(kind)],
+        };
+
+        return (line, errors);
+    }
+
+    let mut nodes = Vec::new();
+
+    for (offset, span) in scan_spans(line, &mut errors) {
+        let mut last = 0;
+
+        // Split the span around any buttons and anchors inside it
+        for caps in DIRECTIVE_REGEX.captures_iter(&span.text) {
+            let directive = caps.get(0).unwrap();
+
+            push_span(
+                &mut nodes,
+                span.attributes,
+                &span.text[last..directive.start()],
+            );
+
+            let label = caps.get(3).map(|label| label.as_str().to_string());
+
+            if let Some(target) = caps.get(1) {
+                nodes.push(Node::Button(Button {
+                    target: target.as_str().parse().unwrap(),
+                    label,
+                    anchor: None,
+                }));
+            } else if let Some(name) = caps.get(2) {
+                match anchors.get(name.as_str()) {
+                    Some(&target) => nodes.push(Node::Button(Button {
+                        target,
+                        label,
+                        anchor: Some(name.as_str().to_string()),
+                    })),
+                    None => errors.push((
+                        offset + name.start(),
+                        format!("Button target '{}' is not a defined anchor", name.as_str()),
+                    )),
+                }
+            } else if directive.as_str() == "þBUTTON" {
+                errors.push((
+                    offset + directive.start(),
+                    "Button is missing its target line or anchor name".to_string(),
+                ));
+            } else if caps.get(4).is_none() {
+                errors.push((
+                    offset + directive.start(),
+                    "Anchor is missing its name".to_string(),
+                ));
+            }
+
+            last = directive.end();
+        }
+
+        if last == 0 {
+            nodes.push(Node::Span(span));
+        } else {
+            push_span(&mut nodes, span.attributes, &span.text[last..]);
+        }
+    }
+
+    (Line { nodes }, errors)
+}
+
+// Split a line on its `~BF` color codes. The first code has no `~`.
+pub fn parse_spans(markup: &str) -> Result<Vec<Span>> {
+    let mut errors = Vec::new();
+    let spans = scan_spans(markup, &mut errors);
+
+    if let Some((_, message)) = errors.first() {
+        bail!("{}", message);
+    }
+
+    Ok(spans.into_iter().map(|(_, span)| span).collect())
+}
+
+// Split a line on its color codes, returning each span with the byte offset of
+// its text. Invalid codes keep the previous color so the text is still shown.
+fn scan_spans(markup: &str, errors: &mut Vec<LineError>) -> Vec<(usize, Span)> {
+    let mut spans = Vec::new();
+    let mut attributes = DEFAULT_ATTRIBUTES;
+    let mut offset = 0;
+
+    for part in markup.split('~') {
+        let start = offset;
+        offset += part.len() + 1;
+
+        // Report problems at the `~` in front of the code
+        let code_start = start.saturating_sub(1);
 
-    // Loop through the line, converting it to ANSI escape codes
-    for part in line.split("~") {
         // Empty part, so skip it
         if part.is_empty() {
             continue;
         }
 
-        result.push_str(&color_to_ansi(&part[0..2])?);
-        result.push_str(&part[2..]);
+        let code_len = part.char_indices().nth(2).map_or(part.len(), |(i, _)| i);
+        let (code, text) = part.split_at(code_len);
+
+        if code.chars().count() < 2 {
+            errors.push((code_start, format!("Truncated color code '{}'", code)));
+            continue;
+        }
+
+        match Attributes::parse(code) {
+            Ok(parsed) => attributes = parsed,
+            Err(_) => errors.push((code_start, format!("Invalid color code '{}'", code))),
+        }
+
+        spans.push((
+            start + code_len,
+            Span {
+                attributes,
+                text: text.to_string(),
+            },
+        ));
     }
 
-    Ok(result)
+    spans
 }
 
-pub fn generate_line(width: u16, padding: u16) -> Result<String> {
+fn push_span(nodes: &mut Vec<Node>, attributes: Attributes, text: &str) {
+    if !text.is_empty() {
+        nodes.push(Node::Span(Span {
+            attributes,
+            text: text.to_string(),
+        }));
+    }
+}
+
+// Expand the buttons and rules of a line into the spans used to draw them
+pub fn layout_line(line: &Line, width: u16, rule: RuleColors) -> Vec<Span> {
+    if line.rule().is_some() {
+        return parse_spans(&generate_line(width, 1, rule)).unwrap();
+    }
+
+    let mut spans = Vec::new();
+
+    for node in &line.nodes {
+        match node {
+            Node::Span(span) => spans.push(span.clone()),
+            Node::Button(button) => spans.extend(button_spans(button)),
+            Node::Rule(_) => {}
+        }
+    }
+
+    spans
+}
+
+// The spans drawing a button and its label
+pub fn button_spans(button: &Button) -> Vec<Span> {
+    let label = button.label.as_deref().unwrap_or(BUTTON_LABEL);
+    parse_spans(&format!("~08>~07>~0F> {} <~07<~08<", label)).unwrap()
+}
+
+// The visible columns each button of a line is drawn at, with its target
+pub fn button_columns(line: &Line) -> Vec<(Range<usize>, usize)> {
+    let mut buttons = Vec::new();
+    let mut column = 0;
+
+    if line.rule().is_some() {
+        return buttons;
+    }
+
+    for node in &line.nodes {
+        match node {
+            Node::Span(span) => column += span.text.chars().count(),
+            Node::Button(button) => {
+                let width = button_spans(button)
+                    .iter()
+                    .map(|span| span.text.chars().count())
+                    .sum::<usize>();
+
+                buttons.push((column..column + width, button.target));
+                column += width;
+            }
+            Node::Rule(_) => {}
+        }
+    }
+
+    buttons
+}
+
+// The text of a line as it appears on screen, without any color codes
+pub fn visible_text(line: &Line, width: u16) -> String {
+    layout_line(line, width, DEFAULT_RULE)
+        .iter()
+        .map(|span| span.text.as_str())
+        .collect()
+}
+
+// Section headings are written as a line starting in color 04 underlined with
+// dashes in color 01, as in the original catalogs
+pub fn find_headings(document: &[Line]) -> Vec<Heading> {
+    document
+        .windows(2)
+        .enumerate()
+        .filter_map(|(i, pair)| {
+            let title = pair[0].text().trim().to_string();
+            let underline = pair[1].text();
+            let underline = underline.trim();
+
+            let is_heading = line_attributes(&pair[0]) == Some(HEADING_ATTRIBUTES)
+                && line_attributes(&pair[1]) == Some(UNDERLINE_ATTRIBUTES)
+                && !title.is_empty()
+                && underline.len() >= 3
+                && underline.chars().all(|c| c == '-');
+
+            is_heading.then_some(Heading { line: i, title })
+        })
+        .collect()
+}
+
+// The color a line starts in
+fn line_attributes(line: &Line) -> Option<Attributes> {
+    line.nodes.iter().find_map(|node| match node {
+        Node::Span(span) => Some(span.attributes),
+        _ => None,
+    })
+}
+
+// Markup for a horizontal rule filling the given width
+pub fn generate_line(width: u16, padding: u16, rule: RuleColors) -> String {
     let mut result = String::new();
 
     if padding > 0 {
@@ -66,13 +508,180 @@ pub fn generate_line(width: u16, padding: u16) -> Result<String> {
         }
     }
 
-    result.push_str("~08──~07──~0F");
+    result.push_str(&format!(
+        "~0{:X}──~0{:X}──~0{:X}",
+        rule[0], rule[1], rule[2]
+    ));
 
-    for _ in 0..width - 8 - (padding * 2) {
+    for _ in 0..width.saturating_sub(8 + padding * 2) {
         result.push('─');
     }
 
-    result.push_str("~07──~08──");
+    result.push_str(&format!("~0{:X}──~0{:X}──", rule[1], rule[0]));
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(code: &str, text: &str) -> Node {
+        Node::Span(Span {
+            attributes: Attributes::parse(code).unwrap(),
+            text: text.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(
+            parse_line("0F  Hello ~04world", &Anchors::new()).0.nodes,
+            vec![span("0F", "  Hello "), span("04", "world")]
+        );
+        assert_eq!(
+            parse_line("03  þBUTTON0250 next", &Anchors::new()).0.nodes,
+            vec![
+                span("03", "  "),
+                Node::Button(Button {
+                    target: 250,
+                    label: None,
+                    anchor: None
+                }),
+                span("03", " next")
+            ]
+        );
+        assert_eq!(
+            parse_line("03þLINE1", &Anchors::new()).0.nodes,
+            vec![Node::Rule(1)]
+        );
+    }
+
+    #[test]
+    fn test_parse_document_diagnostics() {
+        let (lines, diagnostics) = parse_document("0F  ~zzbad ~0\n0é\n03 þBUTTON12 þBUTTON0009");
+
+        assert_eq!(lines[0].nodes, vec![span("0F", "  "), span("0F", "bad ")]);
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.to_string())
+                .collect::<Vec<String>>(),
+            vec![
+                "1:5: error: Invalid color code 'zz'",
+                "1:12: error: Truncated color code '0'",
+                "2:1: error: Invalid color code '0é'",
+                "3:4: error: Button is missing its target line or anchor name",
+                "3:21: error: Button target 9 is past the end of the document (3 lines)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_table_of_contents() {
+        let (lines, _) = parse_document("03þTOC\n03þBUTTON0004\n04One\n01---\n04Two\n01---");
+
+        let contents = lines
+            .iter()
+            .map(|line| {
+                line.nodes
+                    .iter()
+                    .filter_map(|node| match node {
+                        Node::Button(button) => Some((button.target, button.label.clone())),
+                        _ => None,
+                    })
+                    .collect::<Vec<(usize, Option<String>)>>()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 7);
+        assert_eq!(contents[0], vec![(3, Some("One".to_string()))]);
+        assert_eq!(contents[1], vec![(5, Some("Two".to_string()))]);
+        assert_eq!(contents[2], vec![(5, None)]);
+    }
+
+    #[test]
+    fn test_find_headings() {
+        let (lines, _) = parse_document(concat!(
+            "04  1~02) ~0FWhat is CatGen?\n",
+            "01     ---------------\n",
+            "07Name    Size\n",
+            "07------------\n",
+            "04Not underlined in blue\n",
+            "07----------------------",
+        ));
 
-    line_to_ansi(&result)
+        assert_eq!(
+            find_headings(&lines),
+            vec![Heading {
+                line: 0,
+                title: "1) What is CatGen?".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_metadata_lines() {
+        let (lines, diagnostics) =
+            parse_document("þTITLE Acme\n03þBUTTON0003\nþLOGO 0zbad\n04Target");
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0].nodes,
+            vec![Node::Button(Button {
+                target: 1,
+                label: None,
+                anchor: None
+            })]
+        );
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.to_string())
+                .collect::<Vec<String>>(),
+            vec!["3:7: error: Invalid color code '0z'"]
+        );
+
+        // Prose mentioning a directive is still part of the document
+        let (lines, _) = parse_document(
+            "07Set þTITLE and þEXIT here
+07þEXIT 0FBye
+07See þTOC",
+        );
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].text(), "Set þTITLE and þEXIT here");
+        assert_eq!(lines[1].text(), "See þTOC");
+    }
+
+    #[test]
+    fn test_anchors() {
+        let (lines, diagnostics) = parse_document(
+            "03þBUTTON end and þBUTTON nowhere\n04þANCHOR end Index\n07þANCHOR end þANCHOR",
+        );
+
+        assert_eq!(
+            lines[0].nodes,
+            vec![
+                Node::Button(Button {
+                    target: 1,
+                    label: None,
+                    anchor: Some("end".to_string())
+                }),
+                span("03", " and ")
+            ]
+        );
+        assert_eq!(lines[1].nodes, vec![span("04", " Index")]);
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.to_string())
+                .collect::<Vec<String>>(),
+            vec![
+                "1:27: error: Button target 'nowhere' is not a defined anchor",
+                "3:11: error: Anchor 'end' is already defined on line 2",
+                "3:15: error: Anchor is missing its name",
+            ]
+        );
+    }
 }
@@ -0,0 +1,276 @@
+use pdf_writer::{
+    types::{ActionType, AnnotationType, PageMode},
+    Content, Finish, Name, Pdf, Rect, Ref, Str, TextStr,
+};
+
+use crate::{
+    ansi::{ColorDepth, VGA_PALETTE},
+    ast::{Line, Span},
+    layout::{Layout, Row},
+    parser::{find_headings, DEFAULT_RULE},
+};
+
+const PDF_WIDTH: u16 = 80;
+
+// US Letter in points, with Courier at 10pt taking 6pt per character
+const PAGE_WIDTH: f32 = 612.0;
+const PAGE_HEIGHT: f32 = 792.0;
+const FONT_SIZE: f32 = 10.0;
+const CHAR_WIDTH: f32 = 6.0;
+const LINE_HEIGHT: f32 = 12.0;
+const MARGIN_X: f32 = (PAGE_WIDTH - PDF_WIDTH as f32 * CHAR_WIDTH) / 2.0;
+const TEXT_TOP: f32 = PAGE_HEIGHT - 60.0;
+const ROWS_PER_PAGE: usize = 56;
+
+const FONT: Name = Name(b"F1");
+
+// Render the document as a paginated PDF. Buttons become links and section
+// headings become bookmarks.
+pub fn document_to_pdf(document: &[Line], title: &str) -> Vec<u8> {
+    let layout = Layout::new(document, PDF_WIDTH, DEFAULT_RULE, ColorDepth::Basic);
+    let page_count = layout.rows.len().div_ceil(ROWS_PER_PAGE).max(1);
+
+    let mut next_id = Ref::new(1);
+    let catalog_id = next_id.bump();
+    let page_tree_id = next_id.bump();
+    let font_id = next_id.bump();
+    let outline_id = next_id.bump();
+    let page_ids = (0..page_count)
+        .map(|_| next_id.bump())
+        .collect::<Vec<Ref>>();
+
+    // The page and top edge of a row, used as a link destination
+    let destination = |row: usize| {
+        (
+            page_ids[(row / ROWS_PER_PAGE).min(page_count - 1)],
+            row_top(row % ROWS_PER_PAGE),
+        )
+    };
+
+    let mut pdf = Pdf::new();
+
+    pdf.catalog(catalog_id)
+        .pages(page_tree_id)
+        .outlines(outline_id)
+        .page_mode(PageMode::UseOutlines);
+    pdf.pages(page_tree_id)
+        .kids(page_ids.iter().copied())
+        .count(page_count as i32);
+    pdf.type1_font(font_id)
+        .base_font(Name(b"Courier"))
+        .encoding_predefined(Name(b"WinAnsiEncoding"));
+
+    for (i, page_id) in page_ids.iter().enumerate() {
+        let first_row = i * ROWS_PER_PAGE;
+        let rows = layout
+            .rows
+            .iter()
+            .skip(first_row)
+            .take(ROWS_PER_PAGE)
+            .collect::<Vec<&Row>>();
+
+        let content_id = next_id.bump();
+        let content = draw_page(&rows, title, i + 1, page_count);
+        pdf.stream(content_id, &content.finish());
+
+        let mut page = pdf.page(*page_id);
+        page.media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT))
+            .parent(page_tree_id)
+            .contents(content_id);
+        page.resources().fonts().pair(FONT, font_id);
+
+        // Make every button a link to its target
+        let mut annotations = page.annotations();
+
+        for (y, row) in rows.iter().enumerate() {
+            for (columns, target) in &row.buttons {
+                let top = row_top(y);
+                let (target_page, target_top) = destination(layout.row_of(*target, 0));
+
+                let mut link = annotations.push();
+                link.subtype(AnnotationType::Link)
+                    .rect(Rect::new(
+                        MARGIN_X + columns.start as f32 * CHAR_WIDTH,
+                        top - LINE_HEIGHT,
+                        MARGIN_X + columns.end as f32 * CHAR_WIDTH,
+                        top,
+                    ))
+                    .border(0.0, 0.0, 0.0, None);
+                link.action()
+                    .action_type(ActionType::GoTo)
+                    .destination()
+                    .page(target_page)
+                    .xyz(0.0, target_top, None);
+            }
+        }
+
+        annotations.finish();
+        page.finish();
+    }
+
+    // Bookmark each section heading
+    let headings = find_headings(document);
+    let heading_ids = headings
+        .iter()
+        .map(|_| next_id.bump())
+        .collect::<Vec<Ref>>();
+
+    let mut outline = pdf.outline(outline_id);
+    if let (Some(first), Some(last)) = (heading_ids.first(), heading_ids.last()) {
+        outline.first(*first).last(*last);
+    }
+    outline.count(heading_ids.len() as i32);
+    outline.finish();
+
+    for (i, heading) in headings.iter().enumerate() {
+        let (page, top) = destination(layout.row_of(heading.line, 0));
+
+        let mut item = pdf.outline_item(heading_ids[i]);
+        item.title(TextStr(&heading.title)).parent(outline_id);
+
+        if i > 0 {
+            item.prev(heading_ids[i - 1]);
+        }
+        if i + 1 < heading_ids.len() {
+            item.next(heading_ids[i + 1]);
+        }
+
+        item.dest().page(page).xyz(0.0, top, None);
+    }
+
+    pdf.finish()
+}
+
+fn draw_page(rows: &[&Row], title: &str, page: usize, page_count: usize) -> Content {
+    let mut content = Content::new();
+
+    // The document is drawn as it looks on screen, on a black page
+    set_fill(&mut content, 0);
+    content
+        .rect(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT)
+        .fill_nonzero();
+
+    for (y, row) in rows.iter().enumerate() {
+        draw_row(&mut content, &row.spans, row_top(y));
+    }
+
+    // Header and footer
+    set_fill(&mut content, 8);
+    draw_text(&mut content, MARGIN_X, PAGE_HEIGHT - 36.0, title);
+
+    let footer = format!("Page {} of {}", page, page_count);
+    let footer_x = (PAGE_WIDTH - footer.len() as f32 * CHAR_WIDTH) / 2.0;
+    draw_text(&mut content, footer_x, 30.0, &footer);
+
+    content
+}
+
+fn draw_row(content: &mut Content, spans: &[Span], top: f32) {
+    let baseline = top - LINE_HEIGHT + 3.0;
+    let mut column = 0;
+
+    for span in spans {
+        let x = MARGIN_X + column as f32 * CHAR_WIDTH;
+        let length = span.text.chars().count();
+        let width = length as f32 * CHAR_WIDTH;
+
+        if span.attributes.background != 0 {
+            set_fill(content, span.attributes.background);
+            content
+                .rect(x, top - LINE_HEIGHT, width, LINE_HEIGHT)
+                .fill_nonzero();
+        }
+
+        // Rules are drawn as lines since Courier has no box drawing characters
+        if length > 0 && span.text.chars().all(|c| c == '─') {
+            let (r, g, b) = rgb(span.attributes.foreground);
+
+            content
+                .set_stroke_rgb(r, g, b)
+                .set_line_width(1.0)
+                .move_to(x, top - LINE_HEIGHT / 2.0)
+                .line_to(x + width, top - LINE_HEIGHT / 2.0)
+                .stroke();
+        } else if !span.text.trim().is_empty() {
+            set_fill(content, span.attributes.foreground);
+            draw_text(content, x, baseline, &span.text);
+        }
+
+        column += length;
+    }
+}
+
+fn draw_text(content: &mut Content, x: f32, y: f32, text: &str) {
+    content
+        .begin_text()
+        .set_font(FONT, FONT_SIZE)
+        .next_line(x, y)
+        .show(Str(&encode(text)))
+        .end_text();
+}
+
+fn row_top(row: usize) -> f32 {
+    TEXT_TOP - row as f32 * LINE_HEIGHT
+}
+
+fn set_fill(content: &mut Content, color: u8) {
+    let (r, g, b) = rgb(color);
+    content.set_fill_rgb(r, g, b);
+}
+
+fn rgb(color: u8) -> (f32, f32, f32) {
+    let (r, g, b) = VGA_PALETTE[color as usize % 16];
+    (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0)
+}
+
+// Encode text for the WinAnsi encoded standard Courier font, which matches
+// Latin-1 for printable characters. Box drawing falls back to ASCII.
+fn encode(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c {
+            ' '..='~' => c as u8,
+            '\u{A0}'..='\u{FF}' => c as u32 as u8,
+            '─' | '━' | '═' => b'-',
+            '│' | '┃' | '║' => b'|',
+            '░' | '▒' | '▓' | '█' => b'#',
+            '┌' | '┐' | '└' | '┘' | '├' | '┤' | '┬' | '┴' | '┼' => b'+',
+            _ => b'?',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_document;
+
+    // The PDF dictionary containing an entry, up to its closing `>>`
+    fn dictionary<'a>(pdf: &'a str, entry: &str) -> &'a str {
+        let start = pdf.find(entry).unwrap();
+        let end = start + pdf[start..].find(">>").unwrap();
+
+        &pdf[pdf[..start].rfind("<<").unwrap()..end]
+    }
+
+    #[test]
+    fn test_document_to_pdf() {
+        let contents = (0..100)
+            .map(|i| format!("0FLine {}", i))
+            .chain(["04    HEADING".to_string(), "01    -------".to_string()])
+            .chain(["04Games".to_string(), "01-----".to_string()])
+            .chain(["04Utilities".to_string(), "01---------".to_string()])
+            .chain(["03 þBUTTON0100".to_string()])
+            .collect::<Vec<String>>()
+            .join("\n");
+        let (document, _) = parse_document(&contents);
+
+        let pdf = String::from_utf8_lossy(&document_to_pdf(&document, "Test")).to_string();
+
+        assert!(pdf.starts_with("%PDF-"));
+        // Two pages of rows, and one bookmark for each heading
+        assert!(dictionary(&pdf, "/Type /Pages").contains("/Count 2"));
+        assert!(dictionary(&pdf, "/Type /Outlines").contains("/Count 3"));
+        assert!(pdf.contains("/Title (HEADING)"));
+        assert!(pdf.contains("/Subtype /Link"));
+    }
+}
@@ -0,0 +1,165 @@
+use anyhow::{bail, Context, Result};
+use std::{
+    env,
+    fs::OpenOptions,
+    io::{ErrorKind, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+use crate::{
+    ast::{Button, Heading, Line, Node},
+    parser::find_headings,
+};
+
+const PRINT_WIDTH: usize = 80;
+const PRINT_FILE_PREFIX: &str = "catgen-print";
+const SPOOLER: &str = "lpr";
+
+// Print the document with lpr if it is installed, otherwise save it to a new
+// text file in the temporary directory. Returns a message describing where the
+// document went.
+pub fn print_document(document: &[Line]) -> Result<String> {
+    let text = document_to_text(document, PRINT_WIDTH);
+
+    if has_spooler() {
+        spool(&text)?;
+        Ok(format!("Sent to {}", SPOOLER))
+    } else {
+        let path = save(&text)?;
+        Ok(format!("Saved to {}", path.display()))
+    }
+}
+
+// Write to the first numbered file that does not exist yet, so earlier
+// printouts are never overwritten
+fn save(text: &str) -> Result<PathBuf> {
+    let dir = env::temp_dir();
+
+    for number in 1.. {
+        let path = dir.join(format!("{}-{}.txt", PRINT_FILE_PREFIX, number));
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(text.as_bytes())
+                    .with_context(|| format!("Unable to write {}", path.display()))?;
+                return Ok(path);
+            }
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => {
+                return Err(err).with_context(|| format!("Unable to write {}", path.display()))
+            }
+        }
+    }
+
+    bail!(
+        "No free file name for {} in {}",
+        PRINT_FILE_PREFIX,
+        dir.display()
+    )
+}
+
+// Render the document as printer friendly plain text
+pub fn document_to_text(document: &[Line], width: usize) -> String {
+    let headings = find_headings(document);
+    let mut result = String::new();
+
+    for line in document {
+        if line.rule().is_some() {
+            result.push(' ');
+            result.push_str(&"-".repeat(width - 2));
+        } else {
+            let mut text = String::new();
+
+            for node in &line.nodes {
+                match node {
+                    Node::Span(span) => text.push_str(&span.text),
+                    Node::Button(button) => {
+                        let section = section_name(button, &headings);
+
+                        match &button.label {
+                            Some(label) => text.push_str(&format!("[{}, see {}]", label, section)),
+                            None => text.push_str(&format!("[See {}]", section)),
+                        }
+                    }
+                    Node::Rule(_) => {}
+                }
+            }
+
+            result.push_str(text.trim_end());
+        }
+
+        result.push('\n');
+    }
+
+    result
+}
+
+// Name the place a button jumps to by the section it is in, or by its anchor
+// before the first heading
+fn section_name(button: &Button, headings: &[Heading]) -> String {
+    let heading = headings
+        .iter()
+        .rev()
+        .find(|heading| heading.line <= button.target);
+
+    match (heading, &button.anchor) {
+        (Some(heading), _) => format!("\"{}\"", heading.title),
+        (None, Some(anchor)) => format!("\"{}\"", anchor),
+        (None, None) => format!("line {}", button.target + 1),
+    }
+}
+
+fn has_spooler() -> bool {
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).any(|path| path.join(SPOOLER).is_file()))
+        .unwrap_or(false)
+}
+
+fn spool(text: &str) -> Result<()> {
+    let mut child = Command::new(SPOOLER)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Unable to run {}", SPOOLER))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+
+    if !child.wait()?.success() {
+        bail!("{} failed", SPOOLER);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_document;
+
+    #[test]
+    fn test_document_to_text() {
+        let (document, _) = parse_document("0F  Hello ~04world\n03þLINE1\n03  þBUTTON0001\n03");
+
+        assert_eq!(
+            document_to_text(&document, 10),
+            "  Hello world\n --------\n  [See line 2]\n\n"
+        );
+
+        let (document, _) = parse_document(concat!(
+            "03þBUTTON0004 þBUTTON top[Top]\n",
+            "03þANCHOR top\n",
+            "04Games\n",
+            "01-----\n",
+            "03Doom",
+        ));
+
+        assert_eq!(
+            document_to_text(&document, 10).lines().next(),
+            Some("[See \"Games\"] [Top, see \"top\"]")
+        );
+    }
+}
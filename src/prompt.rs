@@ -0,0 +1,154 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+pub enum PromptAction {
+    Continue,
+    Submit(String),
+    Cancel,
+}
+
+// A single line text input with a cursor and history recall
+#[derive(Default)]
+pub struct Prompt {
+    text: Vec<char>,
+    cursor: usize,
+    // Position in the history while recalling, and the text typed before it
+    history_index: Option<usize>,
+    draft: Vec<char>,
+}
+
+impl Prompt {
+    pub fn text(&self) -> String {
+        self.text.iter().collect()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent, history: &[String]) -> PromptAction {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        match key.code {
+            KeyCode::Enter => return PromptAction::Submit(self.text()),
+            KeyCode::Esc => return PromptAction::Cancel,
+
+            KeyCode::Char('u') if control => {
+                self.text.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            KeyCode::Char('a') if control => self.cursor = 0,
+            KeyCode::Char('e') if control => self.cursor = self.text.len(),
+            KeyCode::Char(c) if !control && !key.modifiers.contains(KeyModifiers::ALT) => {
+                self.text.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.text.remove(self.cursor);
+            }
+            KeyCode::Delete if self.cursor < self.text.len() => {
+                self.text.remove(self.cursor);
+            }
+
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.text.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.text.len(),
+
+            KeyCode::Up => self.recall(history, true),
+            KeyCode::Down => self.recall(history, false),
+
+            _ => {}
+        }
+
+        PromptAction::Continue
+    }
+
+    // Step through the history, newest first, returning to the draft at the end
+    fn recall(&mut self, history: &[String], older: bool) {
+        let index = match (self.history_index, older) {
+            (None, true) if !history.is_empty() => {
+                self.draft = self.text.clone();
+                Some(history.len() - 1)
+            }
+            (Some(index), true) => Some(index.saturating_sub(1)),
+            (Some(index), false) if index + 1 < history.len() => Some(index + 1),
+            (Some(_), false) => None,
+            (None, _) => return,
+        };
+
+        self.text = match index {
+            Some(index) => history[index].chars().collect(),
+            None => self.draft.clone(),
+        };
+
+        self.history_index = index;
+        self.cursor = self.text.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(prompt: &mut Prompt, history: &[String], keys: &[KeyEvent]) {
+        for key in keys {
+            prompt.handle_key(*key, history);
+        }
+    }
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn text(text: &str) -> Vec<KeyEvent> {
+        text.chars().map(|c| key(KeyCode::Char(c))).collect()
+    }
+
+    #[test]
+    fn test_prompt_editing() {
+        let mut prompt = Prompt::default();
+
+        press(&mut prompt, &[], &text("catlog"));
+        press(&mut prompt, &[], &[key(KeyCode::Left); 3]);
+        press(&mut prompt, &[], &text("a"));
+        assert_eq!(prompt.text(), "catalog");
+        assert_eq!(prompt.cursor(), 4);
+
+        press(
+            &mut prompt,
+            &[],
+            &[key(KeyCode::Backspace), key(KeyCode::Delete)],
+        );
+        assert_eq!(prompt.text(), "catog");
+
+        press(
+            &mut prompt,
+            &[],
+            &[KeyEvent::new(KeyCode::Char('u'), KeyModifiers::CONTROL)],
+        );
+        assert_eq!(prompt.text(), "og");
+        assert_eq!(prompt.cursor(), 0);
+    }
+
+    #[test]
+    fn test_prompt_history() {
+        let history = vec!["first".to_string(), "second".to_string()];
+        let mut prompt = Prompt::default();
+
+        press(&mut prompt, &history, &text("draft"));
+        press(&mut prompt, &history, &[key(KeyCode::Up)]);
+        assert_eq!(prompt.text(), "second");
+
+        press(&mut prompt, &history, &[key(KeyCode::Up), key(KeyCode::Up)]);
+        assert_eq!(prompt.text(), "first");
+
+        press(
+            &mut prompt,
+            &history,
+            &[key(KeyCode::Down), key(KeyCode::Down)],
+        );
+        assert_eq!(prompt.text(), "draft");
+    }
+}
@@ -1,37 +1,161 @@
-use crate::{parser::PrintableLine, state::ApplicationState};
+use anyhow::{format_err, Result};
+use regex::{Regex, RegexBuilder};
 
-pub fn get_search_state(lines: &Vec<PrintableLine>, search: &str) -> ApplicationState {
-    for (i, line) in lines.iter().enumerate() {
-        if let PrintableLine::Text(line) = line {
-            if line.contains(&search) {
-                return ApplicationState::Search(Some(i), search.to_string());
-            }
-        }
-    }
+use crate::{
+    ast::Line,
+    parser::visible_text,
+    state::{ApplicationState, SearchMatch, SearchOptions, SearchResults},
+};
 
-    return ApplicationState::Search(None, search.to_string());
+// Search the document, starting from the first match at or below the given
+// document line
+pub fn get_search_state(
+    lines: &[Line],
+    width: u16,
+    search: &str,
+    options: SearchOptions,
+    current_line: usize,
+) -> Result<ApplicationState> {
+    let matches = find_matches(lines, width, &build_pattern(search, options)?);
+    let current = matches
+        .iter()
+        .position(|found| found.line >= current_line)
+        .unwrap_or(0);
+
+    Ok(ApplicationState::Search(SearchResults {
+        search: search.to_string(),
+        options,
+        matches,
+        current,
+    }))
 }
 
-pub fn get_next_search_state(
-    lines: &Vec<PrintableLine>,
-    state: ApplicationState,
-) -> ApplicationState {
-    let (current_line, search) = match state {
-        ApplicationState::Search(Some(current_line), search) => (current_line, search),
-        _ => unreachable!(),
+// Every search runs as a regex, with plain text searches escaped
+pub fn build_pattern(search: &str, options: SearchOptions) -> Result<Regex> {
+    let mut pattern = if options.regex {
+        search.to_string()
+    } else {
+        regex::escape(search)
     };
 
+    if options.whole_word {
+        pattern = format!(r"\b(?:{})\b", pattern);
+    }
+
+    RegexBuilder::new(&pattern)
+        .case_insensitive(options.ignore_case)
+        .build()
+        .map_err(|err| {
+            // Regex errors span several lines, ending with the actual problem
+            let message = err.to_string();
+            let reason = message.lines().last().unwrap_or_default();
+
+            format_err!("Invalid pattern: {}", reason.trim_start_matches("error: "))
+        })
+}
+
+// Find every match in the visible text of the document
+pub fn find_matches(lines: &[Line], width: u16, pattern: &Regex) -> Vec<SearchMatch> {
+    let mut matches = Vec::new();
+
     for (i, line) in lines.iter().enumerate() {
-        if i <= current_line {
-            continue;
-        }
+        let text = visible_text(line, width);
 
-        if let PrintableLine::Text(line) = line {
-            if line.contains(&search) {
-                return ApplicationState::Search(Some(i), search.to_string());
-            }
+        // Empty matches have nothing to highlight
+        for found in pattern.find_iter(&text).filter(|found| !found.is_empty()) {
+            matches.push(SearchMatch {
+                line: i,
+                column: text[..found.start()].chars().count(),
+                length: found.as_str().chars().count(),
+            });
         }
     }
 
-    return ApplicationState::Search(None, search.to_string());
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_document;
+
+    fn search(document: &str, search: &str) -> Vec<(usize, usize)> {
+        search_with(document, search, SearchOptions::default())
+    }
+
+    fn search_with(document: &str, search: &str, options: SearchOptions) -> Vec<(usize, usize)> {
+        let (lines, _) = parse_document(document);
+
+        find_matches(&lines, 80, &build_pattern(search, options).unwrap())
+            .iter()
+            .map(|found| (found.line, found.column))
+            .collect()
+    }
+
+    #[test]
+    fn test_search_visible_text() {
+        let document = "0F  Hello ~04wor~0Fld world\n03    þBUTTON0000";
+
+        assert_eq!(search(document, "world"), vec![(0, 8), (0, 14)]);
+        assert_eq!(search(document, "Click Here"), vec![(1, 8)]);
+        assert!(search(document, "1;97").is_empty());
+        assert!(search(document, "~04").is_empty());
+        assert!(search(document, "(").is_empty());
+    }
+
+    #[test]
+    fn test_search_options() {
+        let document = "03 Catalog catalogs CATALOG";
+
+        let ignore_case = SearchOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let whole_word = SearchOptions {
+            whole_word: true,
+            ..ignore_case
+        };
+        let regex = SearchOptions {
+            regex: true,
+            ..Default::default()
+        };
+
+        assert_eq!(search(document, "catalog"), vec![(0, 9)]);
+        assert_eq!(
+            search_with(document, "catalog", ignore_case),
+            vec![(0, 1), (0, 9), (0, 18)]
+        );
+        assert_eq!(
+            search_with(document, "catalog", whole_word),
+            vec![(0, 1), (0, 18)]
+        );
+        assert_eq!(search_with(document, "[A-Z]{3,}", regex), vec![(0, 18)]);
+
+        assert_eq!(
+            build_pattern("(", regex).unwrap_err().to_string(),
+            "Invalid pattern: unclosed group"
+        );
+    }
+
+    #[test]
+    fn test_search_navigation() {
+        let (lines, _) = parse_document("03 one\n03 two\n03 one\n03 one");
+
+        let Ok(ApplicationState::Search(mut results)) =
+            get_search_state(&lines, 80, "one", SearchOptions::default(), 1)
+        else {
+            unreachable!();
+        };
+
+        assert_eq!(results.current_match().map(|found| found.line), Some(2));
+
+        results.next();
+        assert_eq!(results.current_match().map(|found| found.line), Some(3));
+
+        results.next();
+        assert_eq!(results.current_match().map(|found| found.line), Some(0));
+
+        results.previous();
+        assert_eq!(results.current_match().map(|found| found.line), Some(3));
+    }
 }
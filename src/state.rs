@@ -1,9 +1,90 @@
+use std::{fmt, ops::Range};
+
+use crate::{ansi::ColorDepth, branding::Branding, history::History, layout::Layout, theme::Theme};
+
+// A search hit, located by the visible column of the text on screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchMatch {
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SearchOptions {
+    pub ignore_case: bool,
+    pub whole_word: bool,
+    pub regex: bool,
+}
+
+impl SearchOptions {
+    pub fn toggle(&mut self, option: char) {
+        match option {
+            'c' => self.ignore_case = !self.ignore_case,
+            'w' => self.whole_word = !self.whole_word,
+            'r' => self.regex = !self.regex,
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for SearchOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags = [
+            (self.ignore_case, "ignore case"),
+            (self.whole_word, "whole word"),
+            (self.regex, "regex"),
+        ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, name)| *name)
+        .collect::<Vec<&str>>();
+
+        match flags.is_empty() {
+            true => Ok(()),
+            false => write!(f, " ({})", flags.join(", ")),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SearchResults {
+    pub search: String,
+    pub options: SearchOptions,
+    pub matches: Vec<SearchMatch>,
+    pub current: usize,
+}
+
+impl SearchResults {
+    pub fn current_match(&self) -> Option<&SearchMatch> {
+        self.matches.get(self.current)
+    }
+
+    // Move to the next match, wrapping around to the first
+    pub fn next(&mut self) {
+        if !self.matches.is_empty() {
+            self.current = (self.current + 1) % self.matches.len();
+        }
+    }
+
+    // Move to the previous match, wrapping around to the last
+    pub fn previous(&mut self) {
+        if !self.matches.is_empty() {
+            self.current = (self.current + self.matches.len() - 1) % self.matches.len();
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum ApplicationState {
     Normal,
-    Search(Option<usize>, String),
+    Search(SearchResults),
 }
 
+// The smallest terminal the viewer draws itself in
+pub const MIN_WIDTH: u16 = 24;
+pub const MIN_HEIGHT: u16 = 5;
+
 pub struct State {
     pub current_line: usize,
     pub running: bool,
@@ -12,6 +93,20 @@ pub struct State {
     pub document_length: usize,
     pub is_dirty: bool,
     pub application_state: ApplicationState,
+    pub status: Option<String>,
+    pub search_options: SearchOptions,
+    pub search_history: History,
+    // The layout row and index on it of the button with keyboard focus
+    pub focus: Option<(usize, usize)>,
+    // Document positions to return to, kept as line and column so they
+    // survive the document being laid out again
+    pub back: Vec<(usize, usize)>,
+    pub forward: Vec<(usize, usize)>,
+    // Where the scrollbar thumb was grabbed, while it is being dragged
+    pub scrollbar_drag: Option<u16>,
+    pub branding: Branding,
+    pub theme: Theme,
+    pub color_depth: ColorDepth,
 }
 
 impl State {
@@ -24,19 +119,34 @@ impl State {
             document_length,
             is_dirty: true,
             application_state: ApplicationState::Normal,
+            status: None,
+            search_options: SearchOptions::default(),
+            search_history: History::default(),
+            focus: None,
+            back: Vec::new(),
+            forward: Vec::new(),
+            scrollbar_drag: None,
+            branding: Branding::default(),
+            theme: Theme::default(),
+            color_depth: ColorDepth::Basic,
         }
     }
 
     pub fn drawing_height(&self) -> usize {
-        self.height as usize - 2
+        (self.height as usize).saturating_sub(2)
     }
 
-    pub fn scroll_down(&mut self, amount: usize) {
-        self.current_line += amount;
+    // The width the document is laid out at, leaving room for the scrollbar
+    pub fn document_width(&self) -> u16 {
+        self.width.saturating_sub(1)
+    }
 
-        if self.current_line > self.document_length - self.drawing_height() {
-            self.current_line = self.document_length - self.drawing_height();
-        }
+    pub fn is_too_small(&self) -> bool {
+        self.width < MIN_WIDTH || self.height < MIN_HEIGHT
+    }
+
+    pub fn scroll_down(&mut self, amount: usize) {
+        self.current_line = (self.current_line + amount).min(self.max_scroll());
     }
 
     pub fn scroll_up(&mut self, amount: usize) {
@@ -47,21 +157,300 @@ impl State {
     }
 
     pub fn scroll_to(&mut self, pos: usize) {
-        self.current_line = pos as usize;
+        self.current_line = pos.min(self.max_scroll());
+    }
+
+    // Scroll the current search match into view if it is off screen
+    pub fn reveal_search_match(&mut self, layout: &Layout) {
+        let row = match &self.application_state {
+            ApplicationState::Search(results) => match results.current_match() {
+                Some(found) => layout.row_of(found.line, found.column),
+                None => return,
+            },
+            ApplicationState::Normal => return,
+        };
+
+        if row < self.current_line || row >= self.current_line + self.drawing_height() {
+            self.scroll_to(row);
+        }
+    }
+
+    // Jump to a document line, remembering where we came from
+    pub fn follow_link(&mut self, layout: &Layout, line: usize) {
+        self.back.push(layout.position(self.current_line));
+        self.forward.clear();
+        self.scroll_to(layout.row_of(line, 0));
+    }
+
+    // Return to the position before the last jump. Returns false if there is
+    // nowhere to go back to.
+    pub fn go_back(&mut self, layout: &Layout) -> bool {
+        let Some((line, column)) = self.back.pop() else {
+            return false;
+        };
+
+        self.forward.push(layout.position(self.current_line));
+        self.scroll_to(layout.row_of(line, column));
+        true
+    }
+
+    // Redo a jump undone by `go_back`
+    pub fn go_forward(&mut self, layout: &Layout) -> bool {
+        let Some((line, column)) = self.forward.pop() else {
+            return false;
+        };
+
+        self.back.push(layout.position(self.current_line));
+        self.scroll_to(layout.row_of(line, column));
+        true
+    }
+
+    // Move the keyboard focus to the next or previous button on screen,
+    // wrapping around at either end
+    pub fn cycle_focus(&mut self, layout: &Layout, forward: bool) {
+        let buttons = layout
+            .rows
+            .iter()
+            .enumerate()
+            .skip(self.current_line)
+            .take(self.drawing_height())
+            .flat_map(|(y, row)| (0..row.buttons.len()).map(move |i| (y, i)))
+            .collect::<Vec<(usize, usize)>>();
+
+        let current = self
+            .focus
+            .and_then(|focus| buttons.iter().position(|&button| button == focus));
 
-        if self.current_line > self.document_length - self.drawing_height() {
-            self.current_line = self.document_length - self.drawing_height();
+        // Nothing is focused when there are no buttons on screen
+        let next = match (current, forward) {
+            (Some(i), true) => Some((i + 1) % buttons.len()),
+            (Some(i), false) => Some((i + buttons.len() - 1) % buttons.len()),
+            (None, true) => (!buttons.is_empty()).then_some(0),
+            (None, false) => buttons.len().checked_sub(1),
+        };
+
+        self.focus = next.map(|i| buttons[i]);
+    }
+
+    // The target of the focused button, if it is on screen
+    pub fn focused_button(&self, layout: &Layout) -> Option<usize> {
+        let (y, i) = self.focus?;
+        let visible = self.current_line..self.current_line + self.drawing_height();
+
+        visible
+            .contains(&y)
+            .then(|| {
+                layout
+                    .rows
+                    .get(y)?
+                    .buttons
+                    .get(i)
+                    .map(|(_, target)| *target)
+            })
+            .flatten()
+    }
+
+    // The furthest the document can be scrolled down
+    fn max_scroll(&self) -> usize {
+        self.document_length.saturating_sub(self.drawing_height())
+    }
+
+    // The number of rows between the scrollbar arrows
+    fn scrollbar_track(&self) -> usize {
+        self.drawing_height().saturating_sub(2)
+    }
+
+    fn scrollbar_thumb_size(&self) -> usize {
+        let track = self.scrollbar_track();
+
+        // The thumb shows how much of the document fits on screen
+        match self.document_length {
+            0 => track,
+            length => (track * self.drawing_height())
+                .div_ceil(length)
+                .clamp(track.min(1), track),
         }
     }
 
-    pub fn scrollbar_position(&self) -> u16 {
-        ((self.current_line as f32 / (self.document_length - self.height as usize) as f32)
-            * (self.height - 5) as f32) as u16
-            + 1
+    // The screen rows covered by the scrollbar thumb
+    pub fn scrollbar_thumb(&self) -> Range<u16> {
+        let size = self.scrollbar_thumb_size();
+        let free = self.scrollbar_track() - size;
+
+        let start = match self.max_scroll() {
+            0 => 0,
+            max => (self.current_line.min(max) * free + max / 2) / max,
+        };
+
+        (start + 1) as u16..(start + size + 1) as u16
+    }
+
+    // Scroll so the scrollbar thumb starts at the given screen row
+    pub fn drag_scrollbar(&mut self, top: u16) {
+        let free = self.scrollbar_track() - self.scrollbar_thumb_size();
+
+        if free > 0 {
+            let top = (top.saturating_sub(1) as usize).min(free);
+            self.current_line = (top * self.max_scroll() + free / 2) / free;
+        }
     }
 
     pub fn resize(&mut self, width: u16, height: u16) {
         self.width = width;
         self.height = height;
+        self.current_line = self.current_line.min(self.max_scroll());
+
+        // Rows are laid out again, so the focused row no longer applies
+        self.focus = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ansi::ColorDepth,
+        parser::{parse_document, DEFAULT_RULE},
+    };
+    use proptest::prelude::*;
+
+    #[derive(Debug, Clone)]
+    enum Action {
+        ScrollDown(usize),
+        ScrollUp(usize),
+        ScrollTo(usize),
+        Drag(u16),
+        Resize(u16, u16),
+    }
+
+    fn action() -> impl Strategy<Value = Action> {
+        prop_oneof![
+            (0..500usize).prop_map(Action::ScrollDown),
+            (0..500usize).prop_map(Action::ScrollUp),
+            (0..1000usize).prop_map(Action::ScrollTo),
+            (0..300u16).prop_map(Action::Drag),
+            (0..300u16, 0..300u16).prop_map(|(width, height)| Action::Resize(width, height)),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test_state_stays_in_bounds(
+            width in 0..300u16,
+            height in 0..300u16,
+            document_length in 0..1000usize,
+            actions in prop::collection::vec(action(), 0..20),
+        ) {
+            let mut state = State::new((width, height), document_length);
+
+            for action in actions {
+                match action {
+                    Action::ScrollDown(amount) => state.scroll_down(amount),
+                    Action::ScrollUp(amount) => state.scroll_up(amount),
+                    Action::ScrollTo(line) => state.scroll_to(line),
+                    Action::Drag(row) => state.drag_scrollbar(row),
+                    Action::Resize(width, height) => state.resize(width, height),
+                }
+
+                // Never scrolled past the point where the last line is at the bottom
+                prop_assert!(state.current_line <= state.max_scroll());
+
+                // The thumb stays between the arrows
+                let thumb = state.scrollbar_thumb();
+                let track = state.scrollbar_track() as u16;
+                prop_assert!(thumb.start >= 1 && thumb.end <= track + 1);
+                prop_assert_eq!(thumb.is_empty(), track == 0);
+            }
+        }
+
+        #[test]
+        fn test_scrollbar_drag_round_trip(
+            height in 5..100u16,
+            document_length in 0..1000usize,
+            row in 0..100u16,
+        ) {
+            let mut state = State::new((80, height), document_length);
+            let free = state.scrollbar_track() - state.scrollbar_thumb_size();
+
+            state.drag_scrollbar(row);
+
+            // Every thumb position can be reached when there are more lines than rows
+            if state.max_scroll() >= free {
+                let row = (row.max(1) as usize).min(free + 1) as u16;
+                prop_assert_eq!(state.scrollbar_thumb().start, row);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cycle_focus() {
+        let (lines, _) = parse_document("03þBUTTON0002 þBUTTON0001\n07\n03þBUTTON0000\n07");
+        let layout = Layout::new(&lines, 79, DEFAULT_RULE, ColorDepth::Basic);
+        let mut state = State::new((80, 4), layout.rows.len());
+
+        state.cycle_focus(&layout, true);
+        assert_eq!(state.focused_button(&layout), Some(2));
+        state.cycle_focus(&layout, true);
+        assert_eq!(state.focused_button(&layout), Some(1));
+        state.cycle_focus(&layout, true);
+        assert_eq!(state.focused_button(&layout), Some(2));
+        state.cycle_focus(&layout, false);
+        assert_eq!(state.focused_button(&layout), Some(1));
+
+        // Only buttons on screen take focus
+        state.scroll_to(1);
+        assert_eq!(state.focused_button(&layout), None);
+        state.cycle_focus(&layout, false);
+        assert_eq!(state.focused_button(&layout), Some(0));
+    }
+
+    #[test]
+    fn test_scrollbar_thumb() {
+        // 10 rows on screen with 8 of them between the arrows
+        let mut state = State::new((80, 12), 40);
+
+        assert_eq!(state.scrollbar_thumb(), 1..3);
+        state.scroll_to(30);
+        assert_eq!(state.scrollbar_thumb(), 7..9);
+        state.scroll_to(15);
+        assert_eq!(state.scrollbar_thumb(), 4..6);
+
+        // Dragging the thumb to a row scrolls to the matching line
+        state.drag_scrollbar(7);
+        assert_eq!(state.current_line, 30);
+        state.drag_scrollbar(1);
+        assert_eq!(state.current_line, 0);
+        state.drag_scrollbar(100);
+        assert_eq!(state.current_line, 30);
+
+        // Documents that fit on screen fill the track
+        let state = State::new((80, 12), 4);
+        assert_eq!(state.scrollbar_thumb(), 1..9);
+    }
+
+    #[test]
+    fn test_navigation_history() {
+        let (lines, _) = parse_document(&"07\n".repeat(20));
+        let layout = Layout::new(&lines, 79, DEFAULT_RULE, ColorDepth::Basic);
+        let mut state = State::new((80, 7), layout.rows.len());
+
+        state.follow_link(&layout, 10);
+        state.follow_link(&layout, 3);
+        assert_eq!(state.current_line, 3);
+
+        assert!(state.go_back(&layout));
+        assert_eq!(state.current_line, 10);
+        assert!(state.go_back(&layout));
+        assert_eq!(state.current_line, 0);
+        assert!(!state.go_back(&layout));
+
+        assert!(state.go_forward(&layout));
+        assert_eq!(state.current_line, 10);
+
+        // A new jump drops the forward history
+        state.follow_link(&layout, 5);
+        assert!(!state.go_forward(&layout));
+        assert!(state.go_back(&layout));
+        assert_eq!(state.current_line, 10);
     }
 }
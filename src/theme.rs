@@ -0,0 +1,259 @@
+use anyhow::{bail, Context, Result};
+use crossterm::style::{Color, Colors};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{self, Path, PathBuf},
+};
+
+use crate::{
+    ansi::{nearest_256_color, ColorDepth, VGA_PALETTE},
+    parser::RuleColors,
+};
+
+const BUILTIN_THEMES: &str = include_str!("themes.toml");
+
+// DOS color names, indexed by color number
+const COLOR_NAMES: [&str; 16] = [
+    "black",
+    "blue",
+    "green",
+    "cyan",
+    "red",
+    "magenta",
+    "brown",
+    "light-grey",
+    "dark-grey",
+    "light-blue",
+    "light-green",
+    "light-cyan",
+    "light-red",
+    "light-magenta",
+    "yellow",
+    "white",
+];
+
+// One of the 16 DOS colors, written by name in theme files
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct DosColor(pub u8);
+
+impl TryFrom<String> for DosColor {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        COLOR_NAMES
+            .iter()
+            .position(|color| *color == name)
+            .map(|i| DosColor(i as u8))
+            .ok_or_else(|| format!("unknown color '{}'", name))
+    }
+}
+
+impl DosColor {
+    pub fn to_color(self, depth: ColorDepth) -> Color {
+        let (r, g, b) = VGA_PALETTE[self.0 as usize];
+
+        match depth {
+            ColorDepth::TrueColor => Color::Rgb { r, g, b },
+            ColorDepth::Palette256 => Color::AnsiValue(nearest_256_color(self.0)),
+            ColorDepth::Basic => match self.0 {
+                0 => Color::Black,
+                1 => Color::DarkBlue,
+                2 => Color::DarkGreen,
+                3 => Color::DarkCyan,
+                4 => Color::DarkRed,
+                5 => Color::DarkMagenta,
+                6 => Color::DarkYellow,
+                7 => Color::Grey,
+                8 => Color::DarkGrey,
+                9 => Color::Blue,
+                10 => Color::Green,
+                11 => Color::Cyan,
+                12 => Color::Red,
+                13 => Color::Magenta,
+                14 => Color::Yellow,
+                _ => Color::White,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Style {
+    pub fg: DosColor,
+    pub bg: DosColor,
+}
+
+impl Style {
+    const fn new(fg: u8, bg: u8) -> Self {
+        Self {
+            fg: DosColor(fg),
+            bg: DosColor(bg),
+        }
+    }
+
+    pub fn colors(&self, depth: ColorDepth) -> Colors {
+        Colors::new(self.fg.to_color(depth), self.bg.to_color(depth))
+    }
+}
+
+// The colors of everything the viewer draws around the document
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Theme {
+    // Rules, from their ends inwards, in the document and above the footer
+    pub rule: [DosColor; 3],
+    pub title: Style,
+    // Status messages and the search counter
+    pub status: Style,
+    pub helper_bracket: Style,
+    pub helper_key: Style,
+    pub helper_text: Style,
+    pub search_prompt: Style,
+    // The scrollbar track and its arrows
+    pub scrollbar_track: Style,
+    pub scrollbar_thumb: Style,
+    pub outline: Style,
+    pub outline_selected: Style,
+    // The notice shown when the terminal is too small
+    pub notice: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            rule: [DosColor(8), DosColor(7), DosColor(15)],
+            title: Style::new(10, 0),
+            status: Style::new(14, 0),
+            helper_bracket: Style::new(12, 0),
+            helper_key: Style::new(10, 0),
+            helper_text: Style::new(9, 0),
+            search_prompt: Style::new(15, 0),
+            scrollbar_track: Style::new(0, 15),
+            scrollbar_thumb: Style::new(15, 0),
+            outline: Style::new(15, 1),
+            outline_selected: Style::new(0, 3),
+            notice: Style::new(14, 0),
+        }
+    }
+}
+
+impl Theme {
+    pub fn rule_colors(&self) -> RuleColors {
+        self.rule.map(|color| color.0)
+    }
+}
+
+// Find a theme by name among the builtin themes and those in the user's
+// themes file, or load one from a TOML file given by its path
+pub fn load(name: Option<&str>) -> Result<Theme> {
+    let Some(name) = name else {
+        return Ok(Theme::default());
+    };
+
+    // Only a path or a .toml file name is read as a theme file, so a theme name
+    // never picks up a file of the same name in the current directory
+    let is_toml = Path::new(name)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("toml"));
+
+    if name.chars().any(path::is_separator) || is_toml {
+        let contents = fs::read_to_string(name)
+            .with_context(|| format!("Unable to read theme file: {}", name))?;
+
+        return toml::from_str(&contents).with_context(|| format!("Invalid theme file: {}", name));
+    }
+
+    match user_themes_path().filter(|path| path.is_file()) {
+        Some(path) => {
+            let contents = fs::read_to_string(&path)
+                .with_context(|| format!("Unable to read themes file: {}", path.display()))?;
+
+            find_theme(name, Some((&path, &contents)))
+        }
+        None => find_theme(name, None),
+    }
+}
+
+// Look a theme up by name. Themes of the same name in the user's themes file
+// replace the builtin ones.
+fn find_theme(name: &str, user_themes: Option<(&Path, &str)>) -> Result<Theme> {
+    let mut themes = parse_themes(BUILTIN_THEMES).context("Invalid builtin themes")?;
+
+    if let Some((path, contents)) = user_themes {
+        themes.extend(
+            parse_themes(contents)
+                .with_context(|| format!("Invalid themes file: {}", path.display()))?,
+        );
+    }
+
+    match themes.remove(name) {
+        Some(theme) => Ok(theme),
+        None => bail!(
+            "Unknown theme: {} (available: {})",
+            name,
+            themes.into_keys().collect::<Vec<String>>().join(", ")
+        ),
+    }
+}
+
+fn parse_themes(contents: &str) -> Result<BTreeMap<String, Theme>> {
+    Ok(toml::from_str(contents)?)
+}
+
+fn user_themes_path() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(config_dir.join("catgen/themes.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_themes() -> Result<()> {
+        let themes = parse_themes(BUILTIN_THEMES)?;
+
+        assert_eq!(
+            themes.keys().collect::<Vec<&String>>(),
+            vec!["classic", "high-contrast", "light", "monochrome"]
+        );
+        assert_eq!(themes["classic"], Theme::default());
+        assert_eq!(themes["light"].status, Style::new(4, 15));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_themes() {
+        assert!(parse_themes("[mine]\nstatus = { fg = \"pink\", bg = \"black\" }").is_err());
+        assert!(parse_themes("[mine]\nfooter = { fg = \"red\", bg = \"black\" }").is_err());
+    }
+
+    #[test]
+    fn test_find_theme() -> Result<()> {
+        let path = Path::new("themes.toml");
+        let user_themes = "[amber]\ntitle = { fg = \"yellow\", bg = \"black\" }\n[light]\n";
+
+        assert_eq!(find_theme("light", None)?.status, Style::new(4, 15));
+        assert_eq!(
+            find_theme("amber", Some((path, user_themes)))?.title,
+            Style::new(14, 0)
+        );
+        assert_eq!(
+            find_theme("light", Some((path, user_themes)))?,
+            Theme::default()
+        );
+        assert!(find_theme("amber", None).is_err());
+        assert!(find_theme("classic", Some((path, "[mine]\nrule = 3"))).is_err());
+
+        Ok(())
+    }
+}
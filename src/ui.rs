@@ -1,20 +1,71 @@
 use anyhow::Result;
 use crossterm::{
     cursor::{MoveTo, MoveToColumn},
-    style::{Color, Colors, Print, SetBackgroundColor, SetColors, SetForegroundColor},
+    style::{Attribute, Color, Print, SetAttribute, SetBackgroundColor, SetColors},
     terminal::{Clear, ClearType},
     QueueableCommand,
 };
 use std::io::{Stdout, Write};
 
-use crate::{parser::generate_line, state::State};
+use crate::{
+    ansi::{line_to_ansi, spans_to_ansi},
+    ast::Heading,
+    parser::{generate_line, parse_spans},
+    prompt::Prompt,
+    state::{ApplicationState, State, MIN_HEIGHT, MIN_WIDTH},
+};
+
+// The original branding, used when the document does not set its own
+const DEFAULT_TITLE: &str = "Catgen v3.0";
+const DEFAULT_LOGO: &str = "084~07t~0Fh Dimension Softwa~07r~08e";
+const DEFAULT_EXIT_SCREEN: &str = r#"08                        ___ ___ ___________ ___ ___
+08                       Y   Y   Y   _   _   Y   Y   |
+08                       |   l   l___|   |___|   l   |
+08                       l____   |   |   |   |   _   |
+07                           |   |   |   |   |   |   |
+07                           l___|   |   l   l___|   |
+07                                   `---'       `---'
+07      ______   ___ ___ ___ _______ ______  _______ ___ _______ ______
+07     Y   _  \ Y   Y   Y   Y   _   Y   _  \Y   _   Y   Y   _   Y   _  \
+07     |   |   \|   |       |   l___|   |   |   l___l   |   |   |   |   |
+0F     |   |    \   |  \_/  |   __)_    |   l____   |   |   |   |   |   |
+0F     |   l    /   |   |   |   l   |   |   |   l   |   |   l   |   |   |
+0F     l_______/|   l___|   l_______l___|   l_______|   l_______l___|   l
+0F              `---'   `---'           `---'       `---'           `---'
+0F     _______ _______ _______ ___________ ___ ___ _______ _______ _______
+07    Y   _   Y   _   Y   _   Y   _   _   Y   Y   Y   _   Y   _   \   _   Y
+07    |   l___l   |   |   l___l___|   |___l   |   |   l   |   l   /   l___|
+07    l____   |   |   |   __)     |   |   |  / \  |   _   |   _   l   __)_
+08    |   l   |   l   |   |       |   |   |       |   |   |   |   |   l   |
+08    |_______|_______l   |       |   l   l___l___l___|   l___|   l_______|
+08                    `---'       `---'               `---'   `---'
+0F                         http://4ds.simplenet.com/"#;
+
+pub fn draw_search(mut stdout: &Stdout, state: &State, prompt: &Prompt) -> Result<()> {
+    let label = format!("Search for What{}: ", state.search_options);
+    let label_width = label.chars().count();
+
+    // Scroll long searches sideways so the cursor stays on screen
+    let available = (state.width as usize).saturating_sub(label_width).max(1);
+    let offset = prompt.cursor().saturating_sub(available - 1);
+    let text = prompt
+        .text()
+        .chars()
+        .skip(offset)
+        .take(available)
+        .collect::<String>();
 
-pub fn draw_search(mut stdout: &Stdout, state: &State) -> Result<()> {
     stdout
-        .queue(MoveTo(0, state.height - 1))?
+        .queue(MoveTo(0, state.height.saturating_sub(1)))?
         .queue(Clear(ClearType::UntilNewLine))?
-        .queue(SetColors(Colors::new(Color::White, Color::Black)))?
-        .queue(Print("Search for What:"))?;
+        .queue(SetColors(
+            state.theme.search_prompt.colors(state.color_depth),
+        ))?
+        .queue(Print(label))?
+        .queue(Print(text))?
+        .queue(MoveToColumn(
+            (label_width + prompt.cursor() - offset) as u16,
+        ))?;
 
     stdout.flush()?;
     Ok(())
@@ -24,60 +75,183 @@ pub fn draw_footer(mut stdout: &Stdout, state: &State) -> Result<()> {
     stdout
         .queue(MoveTo(0, state.height - 2))?
         .queue(Clear(ClearType::UntilNewLine))?
-        .queue(Print(generate_line(state.width, 0)?))?;
+        .queue(Print(line_to_ansi(
+            &generate_line(state.width, 0, state.theme.rule_colors()),
+            state.color_depth,
+        )?))?;
 
     stdout
         .queue(MoveTo(0, state.height - 1))?
-        .queue(Clear(ClearType::UntilNewLine))?
-        .queue(SetColors(Colors::new(Color::Green, Color::Black)))?
-        .queue(Print("Catgen v3.0"))?;
+        .queue(Clear(ClearType::UntilNewLine))?;
 
-    // Draw company logo in the center
-    stdout
-        .queue(MoveToColumn((state.width - 22) / 2))?
-        .queue(SetForegroundColor(Color::DarkGrey))?
-        .queue(Print("4"))?
-        .queue(SetForegroundColor(Color::Grey))?
-        .queue(Print("t"))?
-        .queue(SetForegroundColor(Color::White))?
-        .queue(Print("h Dimension Softwa"))?
-        .queue(SetForegroundColor(Color::Grey))?
-        .queue(Print("r"))?
-        .queue(SetForegroundColor(Color::DarkGrey))?
-        .queue(Print("e"))?;
+    // Text on the left stops short of the helper text
+    let room = (state.width as usize).saturating_sub(22);
+    let clip = |text: &str| text.chars().take(room).collect::<String>();
+
+    // Temporary messages and search results replace the title and logo
+    if let Some(status) = &state.status {
+        stdout
+            .queue(SetColors(state.theme.status.colors(state.color_depth)))?
+            .queue(Print(clip(status)))?;
+    } else if let ApplicationState::Search(results) = &state.application_state {
+        let counter = match results.matches.len() {
+            0 => format!("No matches for \"{}\"{}", results.search, results.options),
+            count => format!(
+                "\"{}\"{} match {} of {}",
+                results.search,
+                results.options,
+                results.current + 1,
+                count
+            ),
+        };
+
+        stdout
+            .queue(SetColors(state.theme.status.colors(state.color_depth)))?
+            .queue(Print(clip(&counter)))?;
+    } else {
+        stdout
+            .queue(SetColors(state.theme.title.colors(state.color_depth)))?
+            .queue(Print(clip(
+                state.branding.title.as_deref().unwrap_or(DEFAULT_TITLE),
+            )))?;
+
+        draw_logo(stdout, state)?;
+    }
 
     // Draw the helper text
+    let bracket = state.theme.helper_bracket.colors(state.color_depth);
+    let key = state.theme.helper_key.colors(state.color_depth);
+    let text = state.theme.helper_text.colors(state.color_depth);
+
     stdout
         .queue(MoveToColumn(state.width - 21))?
-        .queue(SetForegroundColor(Color::Red))?
+        .queue(SetColors(bracket))?
         .queue(Print("("))?
-        .queue(SetForegroundColor(Color::Green))?
+        .queue(SetColors(key))?
         .queue(Print("↑↓"))?
-        .queue(SetForegroundColor(Color::Red))?
+        .queue(SetColors(bracket))?
         .queue(Print(") ("))?
-        .queue(SetForegroundColor(Color::Green))?
+        .queue(SetColors(key))?
         .queue(Print("S"))?
-        .queue(SetForegroundColor(Color::Red))?
+        .queue(SetColors(bracket))?
         .queue(Print(")"))?
-        .queue(SetForegroundColor(Color::Blue))?
+        .queue(SetColors(text))?
         .queue(Print("earch "))?
-        .queue(SetForegroundColor(Color::Red))?
+        .queue(SetColors(bracket))?
         .queue(Print("("))?
-        .queue(SetForegroundColor(Color::Green))?
+        .queue(SetColors(key))?
         .queue(Print("P"))?
-        .queue(SetForegroundColor(Color::Red))?
+        .queue(SetColors(bracket))?
         .queue(Print(")"))?
-        .queue(SetForegroundColor(Color::Blue))?
+        .queue(SetColors(text))?
         .queue(Print("rint"))?;
 
     stdout.flush()?;
     Ok(())
 }
 
+// Draw company logo in the center
+fn draw_logo(mut stdout: &Stdout, state: &State) -> Result<()> {
+    let spans = match &state.branding.logo {
+        Some(spans) => spans.clone(),
+        None => parse_spans(DEFAULT_LOGO).unwrap(),
+    };
+
+    let title = state.branding.title.as_deref().unwrap_or(DEFAULT_TITLE);
+    let width = spans
+        .iter()
+        .map(|span| span.text.chars().count())
+        .sum::<usize>() as u16;
+    let left = state.width.saturating_sub(width) / 2;
+
+    // Leave it out when it would overlap the title or the helper text
+    if left <= title.chars().count() as u16 || left + width >= state.width - 21 {
+        return Ok(());
+    }
+
+    stdout
+        .queue(MoveToColumn(left))?
+        .queue(Print(spans_to_ansi(&spans, state.color_depth)))?;
+
+    Ok(())
+}
+
+// Draw the section outline as a box in the middle of the document, scrolled
+// so the selected heading is in view
+pub fn draw_outline(
+    mut stdout: &Stdout,
+    state: &State,
+    headings: &[Heading],
+    selected: usize,
+) -> Result<()> {
+    let longest = headings
+        .iter()
+        .map(|heading| heading.title.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    // Keep the box clear of the scrollbar and inside the document area
+    let width = (longest + 4).max(12).min(state.width as usize - 1);
+    let height = (headings.len() + 2).min(state.drawing_height());
+    let left = (state.width as usize - 1 - width) / 2;
+    let top = (state.drawing_height() - height) / 2;
+
+    let visible = height.saturating_sub(2).max(1);
+    let first = selected.saturating_sub(visible - 1);
+
+    let title = " Outline ";
+    let border = width.saturating_sub(2 + title.len());
+    let colors = state.theme.outline.colors(state.color_depth);
+
+    stdout
+        .queue(SetColors(colors))?
+        .queue(MoveTo(left as u16, top as u16))?
+        .queue(Print(format!(
+            "┌{}{}{}┐",
+            "─".repeat(border / 2),
+            title,
+            "─".repeat(border - border / 2)
+        )))?;
+
+    for (y, (i, heading)) in headings
+        .iter()
+        .enumerate()
+        .skip(first)
+        .take(visible)
+        .enumerate()
+    {
+        let title = heading.title.chars().take(width - 4).collect::<String>();
+
+        stdout
+            .queue(SetColors(colors))?
+            .queue(MoveTo(left as u16, (top + 1 + y) as u16))?
+            .queue(Print("│"))?;
+
+        if i == selected {
+            stdout.queue(SetColors(
+                state.theme.outline_selected.colors(state.color_depth),
+            ))?;
+        }
+
+        stdout
+            .queue(Print(format!(" {:width$} ", title, width = width - 4)))?
+            .queue(SetColors(colors))?
+            .queue(Print("│"))?;
+    }
+
+    stdout
+        .queue(MoveTo(left as u16, (top + height - 1) as u16))?
+        .queue(Print(format!("└{}┘", "─".repeat(width - 2))))?
+        .flush()?;
+
+    Ok(())
+}
+
 pub fn draw_scollbar(mut stdout: &Stdout, state: &State) -> Result<()> {
     stdout
-        .queue(SetBackgroundColor(Color::White))?
-        .queue(SetForegroundColor(Color::Black))?
+        .queue(SetColors(
+            state.theme.scrollbar_track.colors(state.color_depth),
+        ))?
         .queue(MoveTo(state.width - 1, 0))?
         .queue(Print("↑"))?
         .queue(MoveTo(state.width - 1, state.height - 3))?
@@ -89,107 +263,69 @@ pub fn draw_scollbar(mut stdout: &Stdout, state: &State) -> Result<()> {
             .queue(Print("░"))?;
     }
 
-    // Draw the scrollbar position
+    // Draw the thumb over the track
+    stdout.queue(SetColors(
+        state.theme.scrollbar_thumb.colors(state.color_depth),
+    ))?;
+
+    for y in state.scrollbar_thumb() {
+        stdout
+            .queue(MoveTo(state.width - 1, y))?
+            .queue(Print(" "))?;
+    }
+
+    stdout.flush()?;
+    Ok(())
+}
+
+// Shown in place of the viewer until the terminal is big enough for it
+pub fn draw_too_small(mut stdout: &Stdout, state: &State) -> Result<()> {
+    let lines = [
+        "Terminal too small".to_string(),
+        format!("Resize to at least {}x{}", MIN_WIDTH, MIN_HEIGHT),
+    ];
+
     stdout
-        .queue(SetBackgroundColor(Color::Black))?
-        .queue(SetForegroundColor(Color::White))?
-        .queue(MoveTo(state.width - 1, state.scrollbar_position()))?
-        .queue(Print(" "))?
-        .flush()?;
+        .queue(SetColors(state.theme.notice.colors(state.color_depth)))?
+        .queue(Clear(ClearType::All))?;
+
+    for (y, line) in lines.iter().take(state.height as usize).enumerate() {
+        let line = line.chars().take(state.width as usize).collect::<String>();
+
+        stdout.queue(MoveTo(0, y as u16))?.queue(Print(line))?;
+    }
+
+    stdout.flush()?;
     Ok(())
 }
 
-pub fn draw_closing_screen(mut stdout: &Stdout) -> Result<()> {
+pub fn draw_closing_screen(mut stdout: &Stdout, state: &State) -> Result<()> {
     stdout
         .queue(Clear(ClearType::All))?
         .queue(MoveTo(0, 0))?
         .queue(SetBackgroundColor(Color::Black))?;
 
-    let lines = [
-        (8, r#"                        ___ ___ ___________ ___ ___"#),
-        (8, r#"                       Y   Y   Y   _   _   Y   Y   |"#),
-        (8, r#"                       |   l   l___|   |___|   l   |"#),
-        (8, r#"                       l____   |   |   |   |   _   |"#),
-        (7, r#"                           |   |   |   |   |   |   |"#),
-        (7, r#"                           l___|   |   l   l___|   |"#),
-        (7, r#"                                   `---'       `---'"#),
-        (
-            7,
-            r#"      ______   ___ ___ ___ _______ ______  _______ ___ _______ ______"#,
-        ),
-        (
-            7,
-            r#"     Y   _  \ Y   Y   Y   Y   _   Y   _  \Y   _   Y   Y   _   Y   _  \"#,
-        ),
-        (
-            7,
-            r#"     |   |   \|   |       |   l___|   |   |   l___l   |   |   |   |   |"#,
-        ),
-        (
-            15,
-            r#"     |   |    \   |  \_/  |   __)_    |   l____   |   |   |   |   |   |"#,
-        ),
-        (
-            15,
-            r#"     |   l    /   |   |   |   l   |   |   |   l   |   |   l   |   |   |"#,
-        ),
-        (
-            15,
-            r#"     l_______/|   l___|   l_______l___|   l_______|   l_______l___|   l"#,
-        ),
-        (
-            15,
-            r#"              `---'   `---'           `---'       `---'           `---'"#,
-        ),
-        (
-            15,
-            r#"     _______ _______ _______ ___________ ___ ___ _______ _______ _______"#,
-        ),
-        (
-            7,
-            r#"    Y   _   Y   _   Y   _   Y   _   _   Y   Y   Y   _   Y   _   \   _   Y"#,
-        ),
-        (
-            7,
-            r#"    |   l___l   |   |   l___l___|   |___l   |   |   l   |   l   /   l___|"#,
-        ),
-        (
-            7,
-            r#"    l____   |   |   |   __)     |   |   |  / \  |   _   |   _   l   __)_"#,
-        ),
-        (
-            8,
-            r#"    |   l   |   l   |   |       |   |   |       |   |   |   |   |   l   |"#,
-        ),
-        (
-            8,
-            r#"    |_______|_______l   |       |   l   l___l___l___|   l___|   l_______|"#,
-        ),
-        (
-            8,
-            r#"                    `---'       `---'               `---'   `---'"#,
-        ),
-        (15, r#"                         http://4ds.simplenet.com/"#),
-    ];
+    let lines = match &state.branding.exit_screen {
+        Some(lines) => lines.clone(),
+        None => DEFAULT_EXIT_SCREEN
+            .lines()
+            .map(|line| parse_spans(line).unwrap())
+            .collect(),
+    };
 
     let mut y = 0;
-    for (dos_color, line) in lines.iter() {
-        let color = match dos_color {
-            7 => Color::Grey,
-            8 => Color::DarkGrey,
-            15 => Color::White,
-            _ => Color::Red,
-        };
-
-        y = y + 1;
+    for spans in lines.iter() {
+        y += 1;
         stdout
-            .queue(SetForegroundColor(color))?
-            .queue(Print(line))?
+            .queue(Print(spans_to_ansi(spans, state.color_depth)))?
             .queue(MoveTo(0, y))?;
     }
 
     // Add an extra line between the logo and the prompt
-    stdout.queue(MoveTo(0, y + 1))?.flush()?;
+    stdout
+        .queue(SetAttribute(Attribute::Reset))?
+        .queue(MoveTo(0, y + 1))?
+        .flush()?;
 
     Ok(())
 }